@@ -0,0 +1,30 @@
+//! Feeds arbitrary bytes to the challenge parsing kernel, once as they are and
+//! once in validating mode, checking the result against the naive reference.
+//!
+//! libFuzzer hands over an exact-size heap copy of every input, so under
+//! AddressSanitizer any read past the end of `data` is reported.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use one_billion_row_challenge::{
+    file::parse_challenge_chunk,
+    output::{Format, Writer},
+    reference,
+};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse_challenge_chunk(data, false);
+
+    // Anything validation accepts must aggregate exactly like the reference
+    let Ok(measurements) = parse_challenge_chunk(data, true) else {
+        return;
+    };
+    let input = std::str::from_utf8(data).expect("validated input is UTF-8");
+    let expected = reference::format(&reference::aggregate(input).unwrap());
+
+    let mut output = Vec::new();
+    Writer::new(Format::Challenge, false)
+        .write(&mut output, &measurements)
+        .unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), expected);
+});
@@ -0,0 +1,166 @@
+use std::{borrow::Cow, collections::HashMap, str::FromStr};
+
+use anyhow::{Context, anyhow, bail};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::measurement::Aggregate;
+
+/// A built-in rewrite applied to every station name before alias lookup.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Normalization {
+    /// Strip leading and trailing whitespace.
+    Trim,
+    /// Unicode canonical composition, so `Sa\u{303}o` and `São` agree.
+    Nfc,
+    /// Unicode lowercase.
+    CaseFold,
+}
+
+impl FromStr for Normalization {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "trim" => Ok(Normalization::Trim),
+            "nfc" => Ok(Normalization::Nfc),
+            "case-fold" | "casefold" | "lowercase" => Ok(Normalization::CaseFold),
+            _ => Err(anyhow!("unknown normalization: {s}")),
+        }
+    }
+}
+
+/// Maps feed-specific spellings onto one canonical station name.
+#[derive(Default)]
+pub struct Normalizer {
+    trim: bool,
+    nfc: bool,
+    case_fold: bool,
+    aliases: HashMap<String, String>,
+}
+
+impl Normalizer {
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        !self.trim && !self.nfc && !self.case_fold && self.aliases.is_empty()
+    }
+
+    /// Enable a built-in normalization. Enable these before loading aliases,
+    /// since alias keys are normalized the same way as station names.
+    pub fn enable(&mut self, normalization: Normalization) {
+        match normalization {
+            Normalization::Trim => self.trim = true,
+            Normalization::Nfc => self.nfc = true,
+            Normalization::CaseFold => self.case_fold = true,
+        }
+    }
+
+    /// Load `alias;canonical` lines. Blank lines are skipped and later lines win.
+    pub fn load_aliases(&mut self, path: &str) -> anyhow::Result<()> {
+        let contents = std::fs::read_to_string(path).context(format!("Failed to read {path}"))?;
+
+        for (i, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let Some((alias, canonical)) = line.split_once(';') else {
+                bail!("{path}:{}: expected `alias;canonical`", i + 1);
+            };
+
+            let alias = self.normalize(alias).into_owned();
+            self.aliases.insert(alias, canonical.to_string());
+        }
+
+        Ok(())
+    }
+
+    fn normalize<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        let mut name = Cow::Borrowed(name);
+        if self.trim {
+            name = match name {
+                Cow::Borrowed(name) => Cow::Borrowed(name.trim()),
+                Cow::Owned(name) => Cow::Owned(name.trim().to_string()),
+            };
+        }
+        if self.nfc && !unicode_normalization::is_nfc(&name) {
+            name = Cow::Owned(name.nfc().collect());
+        }
+        if self.case_fold && name.chars().any(char::is_uppercase) {
+            name = Cow::Owned(name.to_lowercase());
+        }
+        name
+    }
+
+    /// The canonical name for `name`: normalized, then looked up in the alias table.
+    pub fn canonical<'a>(&'a self, name: &'a str) -> Cow<'a, str> {
+        let name = self.normalize(name);
+        match self.aliases.get(name.as_ref()) {
+            Some(canonical) => Cow::Borrowed(canonical),
+            None => name,
+        }
+    }
+
+    /// Rename every station to its canonical name, merging the aggregates of
+    /// stations that end up with the same one.
+    pub fn apply<V: Aggregate>(&self, measurements: Vec<(String, V)>) -> Vec<(String, V)> {
+        if self.is_empty() {
+            return measurements;
+        }
+
+        let mut merged: HashMap<String, V> = HashMap::with_capacity(measurements.len());
+        for (city, measurement) in measurements {
+            let canonical = match self.canonical(&city) {
+                Cow::Borrowed(canonical) if canonical == city => city,
+                canonical => canonical.into_owned(),
+            };
+
+            merged
+                .entry(canonical)
+                .and_modify(|merged| merged.merge(&measurement))
+                .or_insert(measurement);
+        }
+
+        merged.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Normalization, Normalizer};
+    use crate::measurement::{FinalMeasurement, Measurement};
+
+    #[test]
+    fn test_aliases_merge_into_canonical_station() {
+        let path = std::env::temp_dir().join("1brc-test-aliases.csv");
+        std::fs::write(
+            &path,
+            "sao paulo;São Paulo\n\nsp;São Paulo\nSÃO PAULO;São Paulo\n",
+        )
+        .unwrap();
+
+        let mut normalizer = Normalizer::default();
+        normalizer.enable(Normalization::Trim);
+        normalizer.enable(Normalization::Nfc);
+        normalizer.enable(Normalization::CaseFold);
+        normalizer.load_aliases(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let measurements = vec![
+            ("Sao Paulo ".to_string(), Measurement::new(100)),
+            ("Sa\u{303}o Paulo".to_string(), Measurement::new(200)),
+            ("SP".to_string(), Measurement::new(-50)),
+            ("Lima".to_string(), Measurement::new(150)),
+        ];
+
+        let mut result = normalizer.apply(measurements);
+        result.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+        let names: Vec<_> = result.iter().map(|(city, _)| city.as_str()).collect();
+        assert_eq!(names, ["São Paulo", "lima"]);
+
+        let sao_paulo: FinalMeasurement = result[0].1.into();
+        assert_eq!(
+            (sao_paulo.min, sao_paulo.max, sao_paulo.count),
+            (-5.0, 20.0, 3)
+        );
+    }
+}
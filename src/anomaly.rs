@@ -0,0 +1,183 @@
+use std::io::Write;
+
+use anyhow::{anyhow, bail};
+
+use crate::{measurement::Measurement, output::format_tenths, schema::parse_value};
+
+/// Which rows the anomaly pass flags.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Thresholds {
+    /// Flag rows more than this many standard deviations from their station's mean.
+    pub sigma: Option<f64>,
+    /// Flag rows below this value, in tenths.
+    pub min: Option<i32>,
+    /// Flag rows above this value, in tenths.
+    pub max: Option<i32>,
+}
+
+impl Thresholds {
+    /// Parse `MIN,MAX` absolute bounds; either side may be left empty.
+    pub fn set_bounds(&mut self, bounds: &str) -> anyhow::Result<()> {
+        let Some((min, max)) = bounds.split_once(',') else {
+            bail!("bounds must be `MIN,MAX`: {bounds}");
+        };
+        let parse = |value: &str| {
+            if value.trim().is_empty() {
+                return Ok(None);
+            }
+            parse_value(value.as_bytes())
+                .map(Some)
+                .ok_or_else(|| anyhow!("invalid bound: {value}"))
+        };
+
+        self.min = parse(min)?;
+        self.max = parse(max)?;
+        Ok(())
+    }
+
+    /// The range of values a station with this aggregate accepts.
+    pub fn limits(&self, measurement: &Measurement) -> Limits {
+        let mean = measurement.mean_tenths();
+        let stddev = measurement.stddev_tenths();
+
+        let (mut low, mut high) = (i32::MIN, i32::MAX);
+        if let Some(sigma) = self.sigma {
+            // values are whole tenths, so `v < mean - k*sd` iff `v < ceil(mean - k*sd)`
+            low = (mean - sigma * stddev).ceil().max(i32::MIN as f64) as i32;
+            high = (mean + sigma * stddev).floor().min(i32::MAX as f64) as i32;
+        }
+
+        Limits {
+            low: low.max(self.min.unwrap_or(i32::MIN)),
+            high: high.min(self.max.unwrap_or(i32::MAX)),
+            mean,
+            stddev,
+            bounds: (self.min.unwrap_or(i32::MIN), self.max.unwrap_or(i32::MAX)),
+        }
+    }
+}
+
+/// Accepted values of one station, in tenths; anything outside `low..=high` is flagged.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limits {
+    pub low: i32,
+    pub high: i32,
+    pub mean: f64,
+    pub stddev: f64,
+    bounds: (i32, i32),
+}
+
+impl Default for Limits {
+    #[inline(always)]
+    fn default() -> Self {
+        Self {
+            low: i32::MIN,
+            high: i32::MAX,
+            mean: 0.0,
+            stddev: 0.0,
+            bounds: (i32::MIN, i32::MAX),
+        }
+    }
+}
+
+impl Limits {
+    #[inline(always)]
+    pub fn accepts(&self, value: i32) -> bool {
+        (self.low..=self.high).contains(&value)
+    }
+
+    /// Why `value` was flagged: `bounds` if it is outside the absolute bounds, `sigma` otherwise.
+    pub fn reason(&self, value: i32) -> &'static str {
+        if (self.bounds.0..=self.bounds.1).contains(&value) {
+            "sigma"
+        } else {
+            "bounds"
+        }
+    }
+}
+
+/// One flagged row.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Anomaly {
+    /// Byte offset of the start of the row in the input file.
+    pub offset: u64,
+    /// 1-based line number of the row.
+    pub line: u64,
+    pub station: String,
+    pub value: i32,
+    pub limits: Limits,
+    /// The row as it appears in the file, without the line break.
+    pub row: String,
+}
+
+/// Write the anomalies as tab-separated rows under a header, in file order.
+pub fn write(output: &mut impl Write, anomalies: &[Anomaly]) -> std::io::Result<()> {
+    writeln!(
+        output,
+        "line\toffset\tstation\tvalue\tmean\tstddev\tz\treason\trow"
+    )?;
+    for anomaly in anomalies {
+        let limits = &anomaly.limits;
+        let z = if limits.stddev > 0.0 {
+            format!(
+                "{:.2}",
+                (anomaly.value as f64 - limits.mean) / limits.stddev
+            )
+        } else {
+            "inf".to_string()
+        };
+        writeln!(
+            output,
+            "{}\t{}\t{}\t{}\t{:.1}\t{:.2}\t{z}\t{}\t{}",
+            anomaly.line,
+            anomaly.offset,
+            escape(&anomaly.station),
+            format_tenths(anomaly.value as i64),
+            limits.mean / 10.0,
+            limits.stddev / 10.0,
+            limits.reason(anomaly.value),
+            escape(&anomaly.row)
+        )?;
+    }
+    Ok(())
+}
+
+/// Keep every anomaly on one line of the report.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\r', "\\r")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Thresholds;
+    use crate::measurement::Measurement;
+
+    #[test]
+    fn test_limits_combine_sigma_and_bounds() {
+        let mut measurement = Measurement::new(100);
+        for value in [200, 300, 400, 500] {
+            measurement.add(value);
+        }
+
+        let mut thresholds = Thresholds {
+            sigma: Some(1.0),
+            ..Default::default()
+        };
+        // mean 300, stddev ~141.4
+        let limits = thresholds.limits(&measurement);
+        assert_eq!((limits.low, limits.high), (159, 441));
+        assert!(!limits.accepts(100) && limits.accepts(300) && !limits.accepts(500));
+        assert_eq!(limits.reason(500), "sigma");
+
+        thresholds.set_bounds(",40.0").unwrap();
+        let limits = thresholds.limits(&measurement);
+        assert_eq!((limits.low, limits.high), (159, 400));
+        assert_eq!(limits.reason(420), "bounds");
+        assert_eq!(limits.reason(150), "sigma");
+
+        assert!(thresholds.set_bounds("10").is_err());
+    }
+}
@@ -0,0 +1,315 @@
+use anyhow::{Context, anyhow, bail};
+use one_billion_row_challenge::{
+    IN_FILE_PATH,
+    alias::{Normalization, Normalizer},
+    anomaly::Thresholds,
+    filter::{NameFilter, Predicate},
+    group::{Hierarchy, Layout},
+    output::Format,
+    report::{Rank, Report, Statistic},
+    schema::{Schema, parse_delimiter},
+    sort::SortOrder,
+    window::Window,
+};
+
+pub struct Args {
+    pub input: String,
+    pub schema: Schema,
+    pub metrics: Option<Vec<String>>,
+    pub window: Option<Window>,
+    pub first_last: bool,
+    pub anomalies: Option<(String, Thresholds)>,
+    pub diagnostics: bool,
+    pub histogram: Option<String>,
+    pub report: Option<Report>,
+    pub format: Format,
+    pub output: String,
+    pub extra_stats: bool,
+    pub sort: SortOrder,
+    pub reverse: bool,
+    pub names: NameFilter,
+    pub predicates: Vec<Predicate>,
+    pub normalizer: Normalizer,
+    pub group_by: Option<(String, Hierarchy)>,
+    pub group_layout: Layout,
+}
+
+impl Args {
+    pub fn parse() -> anyhow::Result<Self> {
+        Self::parse_from(std::env::args().skip(1))
+    }
+
+    pub fn parse_from(args: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let mut input = IN_FILE_PATH.to_string();
+        let mut delimiter = b';';
+        let mut key_column = 0;
+        let mut value_columns = vec![1];
+        let mut metrics: Option<Vec<String>> = None;
+        let mut timestamp_column = None;
+        let mut window = None;
+        let mut first_last = false;
+        let mut anomalies = None;
+        let mut thresholds = Thresholds::default();
+        let mut diagnostics = false;
+        let mut histogram = None;
+        let mut skip_header = false;
+        let mut rank = None;
+        let mut statistic = Statistic::Avg;
+        let mut format = Format::default();
+        let mut output = None;
+        let mut extra_stats = false;
+        let mut sort = SortOrder::default();
+        let mut reverse = false;
+        let mut names = NameFilter::default();
+        let mut predicates = Vec::new();
+        let mut normalizations = Vec::new();
+        let mut aliases = None;
+        let mut group_by = None;
+        let mut group_layout = Layout::default();
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = |flag: &str| {
+                args.next()
+                    .ok_or_else(|| anyhow!("{flag} requires a value"))
+            };
+
+            match arg.as_str() {
+                "-i" | "--input" => input = value(&arg)?,
+                "--delimiter" => delimiter = parse_delimiter(&value(&arg)?)?,
+                "--key-column" => {
+                    key_column = value(&arg)?
+                        .parse()
+                        .context(format!("{arg} must be a column number"))?
+                }
+                "--value-column" | "--value-columns" => {
+                    value_columns = value(&arg)?
+                        .split(',')
+                        .map(|column| column.trim().parse())
+                        .collect::<Result<_, _>>()
+                        .context(format!("{arg} must be a list of column numbers"))?
+                }
+                "--metrics" => {
+                    metrics = Some(value(&arg)?.split(',').map(str::to_string).collect())
+                }
+                "--timestamp-column" => {
+                    timestamp_column = Some(
+                        value(&arg)?
+                            .parse()
+                            .context(format!("{arg} must be a column number"))?,
+                    )
+                }
+                "--window" => window = Some(value(&arg)?.parse()?),
+                "--skip-header" => skip_header = true,
+                "--first-last" => first_last = true,
+                "--anomalies" => anomalies = Some(value(&arg)?),
+                "--sigma" => {
+                    thresholds.sigma = Some(
+                        value(&arg)?
+                            .parse()
+                            .context(format!("{arg} must be a number"))?,
+                    )
+                }
+                "--bounds" => thresholds.set_bounds(&value(&arg)?)?,
+                "--diagnostics" => diagnostics = true,
+                "--histogram" => {
+                    diagnostics = true;
+                    histogram = Some(value(&arg)?);
+                }
+                "--top" | "--bottom" => {
+                    let n = value(&arg)?
+                        .parse::<usize>()
+                        .context(format!("{arg} must be a number of stations"))?;
+                    let side = if arg == "--top" {
+                        Rank::Top
+                    } else {
+                        Rank::Bottom
+                    };
+                    rank = Some((side, n));
+                }
+                "--by" => statistic = value(&arg)?.parse()?,
+                "--format" => format = value(&arg)?.parse()?,
+                "-o" | "--output" => output = Some(value(&arg)?),
+                "--extra-stats" => extra_stats = true,
+                "--sort" => sort = value(&arg)?.parse()?,
+                "--reverse" => reverse = true,
+                "--include" => names.include(&value(&arg)?)?,
+                "--exclude" => names.exclude(&value(&arg)?)?,
+                "--where" => predicates.push(value(&arg)?.parse()?),
+                "--normalize" => {
+                    for normalization in value(&arg)?.split(',') {
+                        normalizations.push(normalization.trim().parse::<Normalization>()?);
+                    }
+                }
+                "--aliases" => aliases = Some(value(&arg)?),
+                "--group-by" => {
+                    let path = value(&arg)?;
+                    group_by = Some((path.clone(), Hierarchy::load(&path)?));
+                }
+                "--group-layout" => group_layout = value(&arg)?.parse()?,
+                _ => bail!("unknown argument: {arg}"),
+            }
+        }
+
+        // alias keys are normalized like station names, so enable normalizations first
+        let mut normalizer = Normalizer::default();
+        for normalization in normalizations {
+            normalizer.enable(normalization);
+        }
+        if let Some(path) = aliases {
+            normalizer.load_aliases(&path)?;
+        }
+
+        if let Some(metrics) = &metrics
+            && metrics.len() != value_columns.len()
+        {
+            bail!(
+                "--metrics names {} columns, but there are {} value columns",
+                metrics.len(),
+                value_columns.len()
+            );
+        }
+
+        let mut schema = Schema::new(delimiter, key_column, value_columns, skip_header)?;
+        if let Some(column) = timestamp_column {
+            schema = schema.with_timestamp_column(column)?;
+        } else if window.is_some() {
+            bail!("--window needs a --timestamp-column");
+        }
+        // --first-last reads the timestamp column for its first/last seen times
+        if first_last && window.is_some() {
+            bail!("--window can't be combined with --first-last");
+        }
+        if timestamp_column.is_some() && window.is_none() && !first_last {
+            bail!("--timestamp-column needs a --window or --first-last");
+        }
+
+        if group_by.is_some() && rank.is_some() {
+            bail!("--top and --bottom can't be combined with --group-by");
+        }
+        if group_by.is_some() && group_layout == Layout::Nested && !format.is_text() {
+            bail!("--group-layout nested is plain text; use sections with this --format");
+        }
+
+        if anomalies.is_some() {
+            if first_last || timestamp_column.is_some() || schema.value_columns().len() > 1 {
+                bail!(
+                    "--anomalies needs a single value column, without time windows or --first-last"
+                );
+            }
+            if thresholds == Thresholds::default() {
+                thresholds.sigma = Some(3.0);
+            }
+        } else if thresholds != Thresholds::default() {
+            bail!("--sigma and --bounds need an --anomalies output file");
+        }
+
+        Ok(Self {
+            input,
+            window,
+            first_last,
+            anomalies: anomalies.map(|path| (path, thresholds)),
+            diagnostics,
+            histogram,
+            schema,
+            metrics,
+            report: rank.map(|(side, n)| Report::new(side, statistic, n)),
+            output: output.unwrap_or_else(|| format.default_path().to_string()),
+            format,
+            extra_stats,
+            sort,
+            reverse,
+            names,
+            predicates,
+            normalizer,
+            group_by,
+            group_layout,
+        })
+    }
+
+    /// Whether anything asked for reads the standard deviation, which the
+    /// plain aggregation leaves out to keep the challenge path lean.
+    pub fn needs_stddev(&self) -> bool {
+        let stddev = Statistic::StdDev;
+        self.extra_stats
+            || self.anomalies.is_some()
+            || self.report.as_ref().is_some_and(|r| r.statistic == stddev)
+            || self.sort == SortOrder::Statistic(stddev)
+            || self.predicates.iter().any(|p| p.statistic() == stddev)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use one_billion_row_challenge::{
+        output::Format,
+        report::{Rank, Statistic},
+        sort::SortOrder,
+        window::Window,
+    };
+
+    use super::Args;
+
+    fn parse(args: &str) -> anyhow::Result<Args> {
+        Args::parse_from(args.split_whitespace().map(str::to_string))
+    }
+
+    #[test]
+    fn test_defaults() {
+        let args = parse("").unwrap();
+        assert_eq!(args.format, Format::Challenge);
+        assert_eq!(args.output, Format::Challenge.default_path());
+        assert_eq!(args.sort, SortOrder::Bytes);
+        assert!(args.report.is_none() && args.window.is_none() && !args.needs_stddev());
+    }
+
+    #[test]
+    fn test_report_and_output() {
+        let args =
+            parse("-i in.txt --bottom 5 --by range --format csv --sort max --reverse").unwrap();
+        assert_eq!(args.input, "in.txt");
+        let report = args.report.unwrap();
+        assert_eq!(
+            (report.rank, report.statistic, report.n),
+            (Rank::Bottom, Statistic::Range, 5)
+        );
+        assert_eq!(args.output, Format::Csv.default_path());
+        assert_eq!(args.sort, SortOrder::Statistic(Statistic::Max));
+        assert!(args.reverse);
+    }
+
+    #[test]
+    fn test_timestamp_column_only_windows_with_window() {
+        let args = parse("--timestamp-column 0 --key-column 1 --value-column 2 --window daily");
+        assert_eq!(args.unwrap().window, Some(Window::DAILY));
+
+        let args = parse("--timestamp-column 0 --key-column 1 --value-column 2 --first-last");
+        assert_eq!(args.unwrap().window, None);
+    }
+
+    #[test]
+    fn test_rejects_bad_and_conflicting_arguments() {
+        let path = std::env::temp_dir().join("1brc-test-args-groups.csv");
+        std::fs::write(&path, "Berlin;Germany\n").unwrap();
+        let group_by = format!("--group-by {}", path.display());
+
+        for args in [
+            "--top",
+            "--top five",
+            "--by median",
+            "--frobnicate",
+            "--window 1h",
+            "--timestamp-column 2",
+            "--timestamp-column 2 --window 1h --first-last",
+            "--value-columns 1,2 --metrics temperature",
+            "--sigma 2",
+            &format!("--top 3 {group_by}"),
+            &format!("--group-layout nested --format json {group_by}"),
+        ] {
+            assert!(parse(args).is_err(), "{args}");
+        }
+        assert!(parse(&format!("--group-layout nested {group_by}")).is_ok());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
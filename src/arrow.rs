@@ -0,0 +1,222 @@
+use std::{io::Write, sync::Arc};
+
+use arrow_array::{ArrayRef, Float64Array, RecordBatch, StringArray, UInt64Array};
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+
+use crate::measurement::FinalMeasurement;
+
+/// Schema of the batches built by [`to_record_batch`].
+pub fn schema() -> SchemaRef {
+    Arc::new(Schema::new(fields("station")))
+}
+
+/// Schema of the batches built by [`sections_to_record_batch`]: the section
+/// name first, then the [`schema`] columns with the station column renamed.
+pub fn sections_schema(section: &str, label: &str) -> SchemaRef {
+    let section = Field::new(section, DataType::Utf8, false);
+    Arc::new(Schema::new(
+        std::iter::once(section)
+            .chain(fields(label))
+            .collect::<Vec<_>>(),
+    ))
+}
+
+fn fields(label: &str) -> Vec<Field> {
+    vec![
+        Field::new(label, DataType::Utf8, false),
+        Field::new("min", DataType::Float64, false),
+        Field::new("avg", DataType::Float64, false),
+        Field::new("max", DataType::Float64, false),
+        Field::new("count", DataType::UInt64, false),
+    ]
+}
+
+/// Convert from the integer tenths so the f32 representation error never shows up.
+#[inline(always)]
+fn tenths(value: i64) -> f64 {
+    value as f64 / 10.0
+}
+
+fn columns<'m>(
+    measurements: impl Iterator<Item = &'m (String, FinalMeasurement)> + Clone,
+) -> Vec<ArrayRef> {
+    let stations = StringArray::from_iter_values(measurements.clone().map(|(city, _)| city));
+    let min = Float64Array::from_iter_values(
+        measurements
+            .clone()
+            .map(|(_, m)| tenths(m.tenths.min_tenths() as i64)),
+    );
+    let avg = Float64Array::from_iter_values(
+        measurements
+            .clone()
+            .map(|(_, m)| tenths(m.tenths.avg_tenths())),
+    );
+    let max = Float64Array::from_iter_values(
+        measurements
+            .clone()
+            .map(|(_, m)| tenths(m.tenths.max_tenths() as i64)),
+    );
+    let count = UInt64Array::from_iter_values(measurements.map(|(_, m)| m.count as u64));
+
+    vec![
+        Arc::new(stations),
+        Arc::new(min),
+        Arc::new(avg),
+        Arc::new(max),
+        Arc::new(count),
+    ]
+}
+
+pub fn to_record_batch(
+    measurements: &[(String, FinalMeasurement)],
+) -> Result<RecordBatch, ArrowError> {
+    RecordBatch::try_new(schema(), columns(measurements.iter()))
+}
+
+/// Flatten named tables into one batch, with each row's table name in a
+/// leading `section` column.
+pub fn sections_to_record_batch(
+    section: &str,
+    label: &str,
+    sections: &[(String, Vec<(String, FinalMeasurement)>)],
+) -> Result<RecordBatch, ArrowError> {
+    // the array builders need an exact size hint, which `flat_map` can't give
+    let rows: Vec<(&String, &(String, FinalMeasurement))> = sections
+        .iter()
+        .flat_map(|(name, measurements)| measurements.iter().map(move |row| (name, row)))
+        .collect();
+
+    let mut columns = columns(rows.iter().map(|(_, row)| *row));
+    let names = StringArray::from_iter_values(rows.iter().map(|(name, _)| name));
+    columns.insert(0, Arc::new(names));
+    RecordBatch::try_new(sections_schema(section, label), columns)
+}
+
+/// Write the batch as an Arrow IPC file.
+pub fn write_ipc(output: &mut impl Write, batch: &RecordBatch) -> anyhow::Result<()> {
+    let mut writer = arrow_ipc::writer::FileWriter::try_new(output, &batch.schema())?;
+    writer.write(batch)?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+/// Write the batch as a single-row-group Parquet file.
+pub fn write_parquet(output: &mut impl Write, batch: &RecordBatch) -> anyhow::Result<()> {
+    // ArrowWriter needs a `Send` sink; the table is at most a few thousand rows,
+    // so buffering it is cheaper than threading the bound through every writer.
+    let mut buffer = Vec::new();
+    let mut writer = parquet::arrow::ArrowWriter::try_new(&mut buffer, batch.schema(), None)?;
+    writer.write(batch)?;
+    writer.close()?;
+
+    output.write_all(&buffer)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::{Array, Float64Array, RecordBatch, StringArray, UInt64Array};
+
+    use crate::measurement::{FinalMeasurement, Measurement};
+
+    fn measurements() -> Vec<(String, FinalMeasurement)> {
+        let mut abeche = Measurement::new(-123);
+        abeche.add(456);
+        abeche.add(216);
+
+        let mut zurich = Measurement::new(-999);
+        zurich.add(999);
+
+        vec![
+            ("Abéché".to_string(), abeche.into()),
+            ("Zürich".to_string(), zurich.into()),
+        ]
+    }
+
+    fn assert_round_trip(batches: Vec<RecordBatch>) {
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        assert_eq!(batch.schema(), super::schema());
+
+        let column = |i: usize| batch.column(i).as_any();
+        let stations = column(0).downcast_ref::<StringArray>().unwrap();
+        let min = column(1).downcast_ref::<Float64Array>().unwrap();
+        let avg = column(2).downcast_ref::<Float64Array>().unwrap();
+        let max = column(3).downcast_ref::<Float64Array>().unwrap();
+        let count = column(4).downcast_ref::<UInt64Array>().unwrap();
+
+        assert_eq!(stations.len(), 2);
+        assert_eq!(stations.value(0), "Abéché");
+        assert_eq!(stations.value(1), "Zürich");
+        assert_eq!(
+            (min.value(0), avg.value(0), max.value(0)),
+            (-12.3, 18.3, 45.6)
+        );
+        assert_eq!(
+            (min.value(1), avg.value(1), max.value(1)),
+            (-99.9, 0.0, 99.9)
+        );
+        assert_eq!((count.value(0), count.value(1)), (3, 2));
+    }
+
+    #[test]
+    fn test_ipc_round_trip() {
+        let path = std::env::temp_dir().join("1brc-test-round-trip.arrow");
+        let mut file = std::fs::File::create(&path).unwrap();
+        let batch = super::to_record_batch(&measurements()).unwrap();
+        super::write_ipc(&mut file, &batch).unwrap();
+
+        let reader =
+            arrow_ipc::reader::FileReader::try_new(std::fs::File::open(&path).unwrap(), None)
+                .unwrap();
+        assert_round_trip(reader.map(|batch| batch.unwrap()).collect());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_parquet_round_trip() {
+        let path = std::env::temp_dir().join("1brc-test-round-trip.parquet");
+        let mut file = std::fs::File::create(&path).unwrap();
+        let batch = super::to_record_batch(&measurements()).unwrap();
+        super::write_parquet(&mut file, &batch).unwrap();
+
+        let reader = parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(
+            std::fs::File::open(&path).unwrap(),
+        )
+        .unwrap()
+        .build()
+        .unwrap();
+        assert_round_trip(reader.map(|batch| batch.unwrap()).collect());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_sections_flatten_into_one_batch() {
+        let sections = vec![
+            ("humidity".to_string(), measurements()),
+            ("temperature".to_string(), measurements().split_off(1)),
+        ];
+        let batch = super::sections_to_record_batch("metric", "station", &sections).unwrap();
+        assert_eq!(batch.schema(), super::sections_schema("metric", "station"));
+        assert_eq!(batch.schema().field(0).name(), "metric");
+        assert_eq!(batch.schema().field(1).name(), "station");
+
+        let column = |i: usize| batch.column(i).as_any();
+        let metrics = column(0).downcast_ref::<StringArray>().unwrap();
+        let stations = column(1).downcast_ref::<StringArray>().unwrap();
+        let rows: Vec<_> = (0..batch.num_rows())
+            .map(|i| (metrics.value(i), stations.value(i)))
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ("humidity", "Abéché"),
+                ("humidity", "Zürich"),
+                ("temperature", "Zürich"),
+            ]
+        );
+    }
+}
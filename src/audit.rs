@@ -0,0 +1,153 @@
+use std::{collections::BTreeMap, io::Write};
+
+use crate::{
+    measurement::{Aggregate, Measurement},
+    output::{Format, format_tenths, write_rows},
+    window::format_timestamp,
+};
+
+/// One row a station appeared on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sighting {
+    /// Byte offset of the start of the row in the input file.
+    pub offset: u64,
+    pub value: i32,
+    /// The row's timestamp, if the schema has a timestamp column.
+    pub timestamp: Option<i64>,
+}
+
+/// A [`Measurement`] that also remembers the first and last row the station
+/// was seen on.
+///
+/// Sightings carry absolute file offsets, so merging keeps the earlier first
+/// and the later last no matter which order the chunks finish in.
+#[derive(Clone, Copy, Default)]
+pub struct Tracked {
+    pub measurement: Measurement,
+    pub first: Option<Sighting>,
+    pub last: Option<Sighting>,
+}
+
+impl Tracked {
+    /// Record a row. Rows must be added in file order, as a chunk parser does.
+    #[inline(always)]
+    pub fn add(&mut self, sighting: Sighting) {
+        self.measurement.add(sighting.value);
+        if self.first.is_none() {
+            self.first = Some(sighting);
+        }
+        self.last = Some(sighting);
+    }
+}
+
+impl Aggregate for Tracked {
+    fn merge(&mut self, other: &Self) {
+        self.measurement.merge(&other.measurement);
+        self.first = match (self.first, other.first) {
+            (Some(a), Some(b)) => Some(if b.offset < a.offset { b } else { a }),
+            (a, b) => a.or(b),
+        };
+        self.last = match (self.last, other.last) {
+            (Some(a), Some(b)) => Some(if b.offset > a.offset { b } else { a }),
+            (a, b) => a.or(b),
+        };
+    }
+}
+
+/// Write the first/last sightings of every station, in the given order.
+/// `lines` maps row offsets to 1-based line numbers, see `File::line_numbers`.
+/// CSV and TSV get a header row; every other format an aligned table.
+pub fn write(
+    output: &mut impl Write,
+    stations: &[(String, Tracked)],
+    lines: &BTreeMap<u64, u64>,
+    format: Format,
+) -> std::io::Result<()> {
+    let timestamps = stations
+        .iter()
+        .any(|(_, tracked)| tracked.first.is_some_and(|s| s.timestamp.is_some()));
+
+    let mut header = vec![
+        "station",
+        "count",
+        "first_line",
+        "first_offset",
+        "first_value",
+        "last_line",
+        "last_offset",
+        "last_value",
+    ];
+    if timestamps {
+        header.extend(["first_seen", "last_seen"]);
+    }
+
+    let rows: Vec<(&str, Vec<String>)> = stations
+        .iter()
+        .filter_map(|(city, tracked)| {
+            let (first, last) = (tracked.first?, tracked.last?);
+            let mut cells = vec![tracked.measurement.count.to_string()];
+            for sighting in [first, last] {
+                cells.push(
+                    lines
+                        .get(&sighting.offset)
+                        .copied()
+                        .unwrap_or(0)
+                        .to_string(),
+                );
+                cells.push(sighting.offset.to_string());
+                cells.push(format_tenths(sighting.value as i64));
+            }
+            if timestamps {
+                for sighting in [first, last] {
+                    cells.push(sighting.timestamp.map(format_timestamp).unwrap_or_default());
+                }
+            }
+            Some((city.as_str(), cells))
+        })
+        .collect();
+
+    write_rows(output, &header, &rows, format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Sighting, Tracked};
+    use crate::measurement::Aggregate;
+
+    fn tracked(rows: &[(u64, i32)]) -> Tracked {
+        let mut tracked = Tracked::default();
+        for &(offset, value) in rows {
+            tracked.add(Sighting {
+                offset,
+                value,
+                timestamp: None,
+            });
+        }
+        tracked
+    }
+
+    #[test]
+    fn test_merge_order_does_not_matter() {
+        let chunks = [
+            tracked(&[(0, 10), (40, 20)]),
+            tracked(&[]),
+            tracked(&[(100, -5)]),
+            tracked(&[(200, 7), (260, 8)]),
+        ];
+
+        let mut forward = Tracked::default();
+        for chunk in &chunks {
+            forward.merge(chunk);
+        }
+        let mut backward = Tracked::default();
+        for chunk in chunks.iter().rev() {
+            backward.merge(chunk);
+        }
+
+        for merged in [forward, backward] {
+            assert_eq!(merged.measurement.count, 5);
+            assert_eq!(merged.first.map(|s| (s.offset, s.value)), Some((0, 10)));
+            assert_eq!(merged.last.map(|s| (s.offset, s.value)), Some((260, 8)));
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::{collections::BTreeMap, io::Write};
+
+use one_billion_row_challenge::reference::{self, Stats};
+
+use crate::Generator;
+
+/// Min, max, sum and count of one station's rows, in tenths.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Totals {
+    min: i32,
+    max: i32,
+    sum: i64,
+    pub(crate) count: u64,
+}
+
+impl Default for Totals {
+    fn default() -> Self {
+        Self {
+            min: i32::MAX,
+            max: i32::MIN,
+            sum: 0,
+            count: 0,
+        }
+    }
+}
+
+impl Totals {
+    #[inline(always)]
+    pub(crate) fn add(&mut self, tenths: i32) {
+        self.min = self.min.min(tenths);
+        self.max = self.max.max(tenths);
+        self.sum += tenths as i64;
+        self.count += 1;
+    }
+
+    pub(crate) fn merge(&mut self, other: &Self) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.sum += other.sum;
+        self.count += other.count;
+    }
+}
+
+impl Generator {
+    /// Write the challenge output for `totals`, as returned by [`Generator::write`],
+    /// formatted by the reference implementation.
+    pub(crate) fn write_expected(
+        &self,
+        output: &mut impl Write,
+        totals: &[Totals],
+    ) -> std::io::Result<()> {
+        let stations: BTreeMap<String, Stats> = self
+            .stations
+            .iter()
+            .zip(totals)
+            .filter(|(_, totals)| totals.count > 0)
+            .map(|((city, ..), totals)| {
+                let stats = Stats {
+                    min: totals.min as i64,
+                    max: totals.max as i64,
+                    sum: totals.sum,
+                    count: totals.count as i64,
+                };
+                (city.clone(), stats)
+            })
+            .collect();
+        output.write_all(reference::format(&stations).as_bytes())
+    }
+}
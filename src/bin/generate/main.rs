@@ -0,0 +1,1031 @@
+//! Generate a file with an arbitrary number of rows.
+//!
+//! Taken from https://github.com/coriolinus/1brc/blob/main/src/bin/generate.rs
+//!
+//! See reference implementation: https://github.com/gunnarmorling/1brc/blob/main/src/main/java/dev/morling/onebrc/CreateMeasurements.java
+//!
+//! Options and profiles are described in the README.
+
+mod expected;
+mod names;
+mod profiles;
+mod rng;
+mod sink;
+
+use anyhow::{Context, Result, anyhow, bail};
+use flate2::{Compression, write::GzEncoder};
+use std::{
+    collections::hash_map::RandomState,
+    fs::File,
+    hash::{BuildHasher, Hasher},
+    io::{BufWriter, IsTerminal, Write},
+    ops::RangeInclusive,
+    path::Path,
+};
+
+use expected::Totals;
+use names::{
+    DATA, MAX_NAME_BYTES, edge_name, key_length_names, parse_lengths, parse_stations,
+    synthesize_names,
+};
+use profiles::{
+    BOUNDARY_TENTHS, Distribution, Format, Frequency, Profile, write_lenient, write_tenths,
+};
+use rng::{Xoshiro256StarStar, mix64};
+use sink::{Sink, write_at};
+
+/// How many rows to generate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Target {
+    Rows(usize),
+    /// As many whole rows as fit in this many bytes.
+    Bytes(u64),
+}
+
+/// What [`Generator::write`] wrote.
+struct Written {
+    rows: u64,
+    bytes: u64,
+    /// Per station, in [`Generator`] order.
+    totals: Vec<Totals>,
+}
+
+/// Rows drawn from one RNG. Part of the file format: changing it changes
+/// every file with more rows than this.
+const BLOCK_ROWS: usize = 1 << 20;
+
+/// Most blocks every thread formats before the results are written out.
+const BLOCKS_PER_RUN: usize = 4;
+
+/// Bytes the formatted runs of all threads may take together. Long names or
+/// many threads get fewer threads and shorter runs, down to a single block.
+const MAX_BUFFERED: usize = 1 << 30;
+
+/// Largest temperature magnitude the challenge allows, in tenths.
+const MAX_TENTHS: i32 = 999;
+
+fn main() -> Result<()> {
+    let mut rows = None;
+    let mut size = None;
+    let mut output = "measurements.txt".to_string();
+    let mut gzip = false;
+    let mut expected = None;
+    let mut seed = None;
+    let mut threads = None;
+    let mut format = Format::default();
+    let mut stations = None;
+    let mut name_lengths = None;
+    let mut profile = Profile::default();
+    let mut station_file = None;
+    let mut distribution = Distribution::default();
+    let mut frequency = Frequency::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| {
+            args.next()
+                .ok_or_else(|| anyhow!("{flag} requires a value"))
+        };
+
+        match arg.as_str() {
+            "--size" => size = Some(parse_size(&value(&arg)?)?),
+            "-o" | "--output" => output = value(&arg)?,
+            "--gzip" => gzip = true,
+            "--expected" => expected = Some(value(&arg)?),
+            "--seed" => {
+                seed = Some(
+                    value(&arg)?
+                        .parse::<u64>()
+                        .context("--seed must be a u64")?,
+                );
+            }
+            "--threads" => {
+                threads = Some(
+                    value(&arg)?
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| anyhow!("--threads must be a positive number"))?,
+                );
+            }
+            "--format" => format = value(&arg)?.parse()?,
+            "--stations" => {
+                stations = Some(
+                    value(&arg)?
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| anyhow!("--stations must be a positive number"))?,
+                );
+            }
+            "--name-length" => name_lengths = Some(parse_lengths(&value(&arg)?)?),
+            "--profile" => profile = value(&arg)?.parse()?,
+            "--station-file" => station_file = Some(value(&arg)?),
+            "--distribution" => distribution = value(&arg)?.parse()?,
+            "--station-frequency" => frequency = value(&arg)?.parse()?,
+            _ if rows.is_none() => {
+                rows = Some(
+                    arg.parse::<usize>()
+                        .context("must be able to parse as usize")?,
+                )
+            }
+            _ => bail!("unknown argument: {arg}"),
+        }
+    }
+    let target = match (rows, size) {
+        (Some(rows), None) => Target::Rows(rows),
+        (None, Some(size)) => Target::Bytes(size),
+        (Some(_), Some(_)) => bail!("give either a row count or --size, not both"),
+        (None, None) => bail!("requires a row count or --size"),
+    };
+    let gzip = gzip || output.ends_with(".gz");
+    let expected = expected.or_else(|| {
+        let data = output.strip_suffix(".gz").unwrap_or(&output);
+        (output != "-").then(|| Path::new(data).with_extension("out").display().to_string())
+    });
+    if expected.as_ref() == Some(&output) {
+        bail!("the expected output would overwrite the rows at {output}");
+    }
+    if name_lengths.is_some() && stations.is_none() {
+        bail!("--name-length requires --stations");
+    }
+    if stations.is_some() && station_file.is_some() {
+        bail!("--stations and --station-file can't be combined");
+    }
+    if (stations.is_some() || station_file.is_some()) && profile.has_own_stations() {
+        bail!("the key-lengths and boundaries profiles bring their own stations");
+    }
+    let seed = seed.unwrap_or_else(|| {
+        // `RandomState` keys are drawn from the OS for every process
+        let seed = RandomState::new().build_hasher().finish();
+        eprintln!("seed: {seed}");
+        seed
+    });
+    let threads =
+        threads.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+
+    let generator = match stations {
+        Some(count) => {
+            let lengths = name_lengths.unwrap_or(1..=MAX_NAME_BYTES);
+            Generator::synthetic(seed, BLOCK_ROWS, count, lengths)?
+        }
+        None => match &station_file {
+            Some(path) => Generator::load(seed, BLOCK_ROWS, path)?,
+            None => Generator::new(seed, BLOCK_ROWS),
+        },
+    };
+
+    let generator = generator
+        .with_format(format)
+        .with_profile(profile)
+        .with_distribution(distribution)
+        .with_frequency(frequency)
+        .with_progress(std::io::stderr().is_terminal());
+
+    let written = if output == "-" || gzip {
+        let stream: Box<dyn Write> = if output == "-" {
+            Box::new(std::io::stdout().lock())
+        } else {
+            Box::new(File::create(&output).context(format!("Failed to create {output}"))?)
+        };
+        if gzip {
+            let mut encoder = GzEncoder::new(BufWriter::new(stream), Compression::default());
+            let written = generator.write(Sink::Stream(&mut encoder), target, threads)?;
+            encoder
+                .finish()
+                .and_then(|mut stream| stream.flush())
+                .context(format!("Failed to write to {output}"))?;
+            written
+        } else {
+            let mut stream = stream;
+            let written = generator.write(Sink::Stream(&mut stream), target, threads)?;
+            stream
+                .flush()
+                .context(format!("Failed to write to {output}"))?;
+            written
+        }
+    } else {
+        let file = File::create(&output).context(format!("Failed to create {output}"))?;
+        generator.write(Sink::File(&file), target, threads)?
+    };
+
+    if let Some(path) = expected {
+        let file = File::create(&path).context(format!("Failed to create {path}"))?;
+        let mut file = BufWriter::new(file);
+        generator
+            .write_expected(&mut file, &written.totals)
+            .and_then(|_| file.flush())
+            .context(format!("Failed to write to {path}"))?;
+    }
+
+    Ok(())
+}
+
+/// Draws rows in blocks of [`BLOCK_ROWS`], each from its own RNG sub-seeded by
+/// the block index, so threads can format blocks in any order.
+struct Generator {
+    seed: u64,
+    block_rows: usize,
+    format: Format,
+    profile: Profile,
+    distribution: Distribution,
+    /// Cumulative probability of picking each station, for Zipf frequencies.
+    cumulative: Option<Vec<f64>>,
+    /// Every station with its mean and its own spread.
+    stations: Vec<(String, f64, f64)>,
+    /// The seed's RNG after drawing the spreads, which produces the first block.
+    first_block: Xoshiro256StarStar,
+    /// Report progress on stderr.
+    progress: bool,
+    /// Bound on the formatted bytes held at once, see [`MAX_BUFFERED`].
+    max_buffered: usize,
+}
+
+impl Generator {
+    /// A generator for the real stations in [`DATA`].
+    fn new(seed: u64, block_rows: usize) -> Self {
+        let stations = DATA
+            .iter()
+            .map(|&(city, mean)| (city.to_string(), mean, None))
+            .collect();
+        Self::with_stations(seed, block_rows, stations, Xoshiro256StarStar::new(seed))
+    }
+
+    /// A generator for `count` synthetic stations, with name lengths in
+    /// bytes drawn uniformly from `lengths`.
+    fn synthetic(
+        seed: u64,
+        block_rows: usize,
+        count: usize,
+        lengths: RangeInclusive<usize>,
+    ) -> Result<Self> {
+        let mut rng = Xoshiro256StarStar::new(seed);
+        let names = synthesize_names(&mut rng, count, lengths)?;
+        // means in the range of the real stations'
+        let stations = names
+            .into_iter()
+            .map(|name| (name, -10.0 + 40.0 * rng.next_f64(), None))
+            .collect();
+        Ok(Self::with_stations(seed, block_rows, stations, rng))
+    }
+
+    /// A generator for the `station;mean[;stddev]` lines in the file at `path`.
+    fn load(seed: u64, block_rows: usize, path: &str) -> Result<Self> {
+        let contents = std::fs::read_to_string(path).context(format!("Failed to read {path}"))?;
+        let stations = parse_stations(&contents).context(format!("Failed to parse {path}"))?;
+        Ok(Self::with_stations(
+            seed,
+            block_rows,
+            stations,
+            Xoshiro256StarStar::new(seed),
+        ))
+    }
+
+    fn with_stations(
+        seed: u64,
+        block_rows: usize,
+        stations: Vec<(String, f64, Option<f64>)>,
+        mut rng: Xoshiro256StarStar,
+    ) -> Self {
+        // stations without a spread get their own, itself drawn around 10 degrees
+        let stations = stations
+            .into_iter()
+            .map(|(city, mean, std_dev)| {
+                let std_dev = std_dev.unwrap_or_else(|| 10.0 + 2.5 * rng.next_normal());
+                (city, mean, std_dev)
+            })
+            .collect();
+
+        Self {
+            seed,
+            block_rows,
+            format: Format::default(),
+            profile: Profile::default(),
+            distribution: Distribution::default(),
+            cumulative: None,
+            stations,
+            first_block: rng,
+            progress: false,
+            max_buffered: MAX_BUFFERED,
+        }
+    }
+
+    fn with_format(self, format: Format) -> Self {
+        Self { format, ..self }
+    }
+
+    /// Switch to `profile`, replacing the stations if it brings its own.
+    fn with_profile(self, profile: Profile) -> Self {
+        let stations = match profile {
+            Profile::KeyLengths => key_length_names(),
+            Profile::Boundaries => (1..=MAX_NAME_BYTES)
+                .map(|len| edge_name(len, len))
+                .collect(),
+            _ => return Self { profile, ..self },
+        };
+        Self {
+            profile,
+            stations: stations.into_iter().map(|name| (name, 0.0, 0.0)).collect(),
+            ..self
+        }
+    }
+
+    fn with_progress(self, progress: bool) -> Self {
+        Self { progress, ..self }
+    }
+
+    fn with_distribution(self, distribution: Distribution) -> Self {
+        Self {
+            distribution,
+            ..self
+        }
+    }
+
+    /// Pick stations with `frequency`, in the order they are listed.
+    fn with_frequency(self, frequency: Frequency) -> Self {
+        let cumulative = match frequency {
+            Frequency::Uniform => None,
+            Frequency::Zipf(exponent) => {
+                let weight = |rank: f64| {
+                    // exact IEEE division for the default exponent
+                    if exponent == 1.0 {
+                        1.0 / rank
+                    } else {
+                        rank.powf(-exponent)
+                    }
+                };
+                let weights: Vec<f64> = (1..=self.stations.len())
+                    .map(|rank| weight(rank as f64))
+                    .collect();
+                let total: f64 = weights.iter().sum();
+                let mut sum = 0.0;
+                Some(
+                    weights
+                        .into_iter()
+                        .map(|weight| {
+                            sum += weight;
+                            sum / total
+                        })
+                        .collect(),
+                )
+            }
+        };
+        Self { cumulative, ..self }
+    }
+
+    /// A random station, following the station frequency.
+    #[inline(always)]
+    fn pick_station(&self, rng: &mut Xoshiro256StarStar) -> usize {
+        match &self.cumulative {
+            None => rng.next_below(self.stations.len()),
+            Some(cumulative) => {
+                let u = rng.next_f64();
+                cumulative
+                    .partition_point(|&p| p <= u)
+                    .min(self.stations.len() - 1)
+            }
+        }
+    }
+
+    /// The RNG for `block`; blocks after the first get independent sub-seeds.
+    fn block_rng(&self, block: usize) -> Xoshiro256StarStar {
+        if block == 0 {
+            return self.first_block.clone();
+        }
+        Xoshiro256StarStar::new(mix64(self.seed ^ mix64(block as u64)))
+    }
+
+    /// The station and temperature of row number `row`.
+    #[inline(always)]
+    fn draw(&self, row: usize, rng: &mut Xoshiro256StarStar) -> (usize, i32) {
+        let stations = self.stations.len();
+        let station = match self.profile {
+            Profile::Weather => self.pick_station(rng),
+            Profile::Hot if rng.next_below(100) == 0 => rng.next_below(stations),
+            Profile::Hot => 0,
+            Profile::Uniform | Profile::Temperatures | Profile::KeyLengths => row % stations,
+            Profile::Boundaries => {
+                let tenths = BOUNDARY_TENTHS[row / stations % BOUNDARY_TENTHS.len()];
+                return (row % stations, tenths);
+            }
+        };
+        let tenths = match self.profile {
+            Profile::Temperatures => (row % (2 * MAX_TENTHS as usize + 1)) as i32 - MAX_TENTHS,
+            Profile::KeyLengths => rng.next_below(2 * MAX_TENTHS as usize + 1) as i32 - MAX_TENTHS,
+            _ => {
+                let (_, mean, std_dev) = self.stations[station];
+                let temp = mean + std_dev * self.distribution.sample(rng);
+                ((temp * 10.0).round() as i32).clamp(-MAX_TENTHS, MAX_TENTHS)
+            }
+        };
+        (station, tenths)
+    }
+
+    /// Append the `rows` lines of `block` to `buffer`, tallying them in `totals`.
+    fn format_block(&self, block: usize, rows: usize, buffer: &mut Vec<u8>, totals: &mut [Totals]) {
+        let mut rng = self.block_rng(block);
+        // spellings come from their own stream, so both formats hold the same values
+        let mut spellings = Xoshiro256StarStar::new(mix64(!self.seed ^ mix64(block as u64)));
+        let first_row = block * self.block_rows;
+        for row in first_row..first_row + rows {
+            let (station, tenths) = self.draw(row, &mut rng);
+            totals[station].add(tenths);
+
+            buffer.extend_from_slice(self.stations[station].0.as_bytes());
+            buffer.push(b';');
+            match self.format {
+                Format::Challenge => write_tenths(buffer, tenths),
+                Format::Lenient => write_lenient(buffer, tenths, spellings.next_below(5)),
+            }
+            buffer.push(b'\n');
+        }
+    }
+
+    /// Upper bound on the bytes of one formatted block.
+    fn block_bytes(&self) -> usize {
+        let longest = self.stations.iter().map(|(name, ..)| name.len()).max();
+        // `;`, the longest spelling (`-99.90`) and `\n`
+        self.block_rows * (longest.unwrap_or(0) + 8)
+    }
+
+    /// How many of `threads` threads to use, and the most blocks each may
+    /// format per run, to stay within `max_buffered` bytes.
+    fn plan(&self, threads: usize) -> (usize, usize) {
+        let blocks = (self.max_buffered / self.block_bytes()).max(1);
+        let threads = threads.min(blocks);
+        (threads, (blocks / threads).min(BLOCKS_PER_RUN))
+    }
+
+    /// Write the rows of `target` to `sink` using up to `threads` threads.
+    ///
+    /// Every thread formats a run of consecutive blocks into its own buffer;
+    /// file sinks then get all runs written at once, at their offsets.
+    fn write(&self, mut sink: Sink<'_>, target: Target, threads: usize) -> Result<Written> {
+        // with a byte target the row count is only known once the last run is formatted
+        let mut rows = match target {
+            Target::Rows(rows) => Some(rows),
+            Target::Bytes(_) => None,
+        };
+
+        let (threads, max_run) = self.plan(threads);
+        let mut buffers = vec![Vec::new(); threads];
+        let mut totals = vec![vec![Totals::default(); self.stations.len()]; threads];
+        let mut written = Written {
+            rows: 0,
+            bytes: 0,
+            totals: vec![Totals::default(); self.stations.len()],
+        };
+        let mut next_block = 0;
+        loop {
+            let blocks = rows.map_or(usize::MAX, |rows| rows.div_ceil(self.block_rows));
+            if next_block >= blocks {
+                break;
+            }
+            let rows_in = |block: usize| {
+                rows.map_or(self.block_rows, |rows| {
+                    self.block_rows.min(rows - block * self.block_rows)
+                })
+            };
+            let run = (blocks - next_block).div_ceil(threads).min(max_run);
+
+            // format: thread t takes the t-th run of consecutive blocks
+            std::thread::scope(|s| {
+                for (t, (buffer, totals)) in buffers.iter_mut().zip(&mut totals).enumerate() {
+                    let start = (next_block + t * run).min(blocks);
+                    let end = (start + run).min(blocks);
+                    s.spawn(move || {
+                        buffer.clear();
+                        totals.fill(Totals::default());
+                        for block in start..end {
+                            self.format_block(block, rows_in(block), buffer, totals);
+                        }
+                    });
+                }
+            });
+
+            let bytes: u64 = buffers.iter().map(|buffer| buffer.len() as u64).sum();
+            if let Target::Bytes(limit) = target
+                && rows.is_none()
+                && written.bytes + bytes >= limit
+            {
+                // the last runs: count the whole rows that fit and format them again
+                let mut room = (limit - written.bytes) as usize;
+                let mut fit = written.rows as usize;
+                for buffer in &buffers {
+                    let kept = &buffer[..room.min(buffer.len())];
+                    fit += kept.iter().filter(|&&b| b == b'\n').count();
+                    room -= kept.len();
+                }
+                rows = Some(fit);
+                continue;
+            }
+
+            match &mut sink {
+                Sink::File(file) => {
+                    let file: &File = file;
+                    // lay the runs out back to back, then write them all in parallel
+                    let mut offsets = Vec::with_capacity(threads);
+                    let mut offset = written.bytes;
+                    for buffer in &buffers {
+                        offsets.push(offset);
+                        offset += buffer.len() as u64;
+                    }
+                    file.set_len(offset).context("extending output file")?;
+                    std::thread::scope(|s| {
+                        let handles: Vec<_> = buffers
+                            .iter()
+                            .zip(&offsets)
+                            .map(|(buffer, &at)| s.spawn(move || write_at(file, buffer, at)))
+                            .collect();
+                        handles
+                            .into_iter()
+                            .try_for_each(|h| h.join().expect("writer thread panicked"))
+                    })
+                    .context("writing data")?;
+                }
+                Sink::Stream(output) => {
+                    for buffer in &buffers {
+                        output.write_all(buffer).context("writing data")?;
+                    }
+                }
+            }
+
+            for thread in &totals {
+                for (station, other) in written.totals.iter_mut().zip(thread) {
+                    written.rows += other.count;
+                    station.merge(other);
+                }
+            }
+            written.bytes += bytes;
+            next_block += run * threads;
+
+            if self.progress {
+                let done = match target {
+                    Target::Rows(rows) => written.rows as f64 / rows as f64,
+                    Target::Bytes(bytes) => written.bytes as f64 / bytes as f64,
+                };
+                eprint!(
+                    "\r{} rows, {:.1} MiB ({:.0}%)",
+                    written.rows,
+                    written.bytes as f64 / (1 << 20) as f64,
+                    100.0 * done
+                );
+            }
+        }
+
+        if self.progress {
+            eprintln!();
+        }
+        Ok(written)
+    }
+}
+
+/// Parse `--size`: a byte count with an optional decimal or binary unit.
+fn parse_size(s: &str) -> Result<u64> {
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(digits);
+    let scale: u64 = match unit.trim() {
+        "" | "B" => 1,
+        "KB" => 1_000,
+        "MB" => 1_000_000,
+        "GB" => 1_000_000_000,
+        "TB" => 1_000_000_000_000,
+        "K" | "KiB" => 1 << 10,
+        "M" | "MiB" => 1 << 20,
+        "G" | "GiB" => 1 << 30,
+        "T" | "TiB" => 1 << 40,
+        _ => bail!("unknown size unit in {s} (expected KB, MB, GB, TB or KiB, MiB, GiB, TiB)"),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(scale))
+        .ok_or_else(|| anyhow!("invalid size: {s}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BLOCK_ROWS, BLOCKS_PER_RUN, BOUNDARY_TENTHS, Format, Frequency, Generator, MAX_NAME_BYTES,
+        Profile, Sink, Target, Xoshiro256StarStar, names::KEY_LENGTHS, parse_lengths, parse_size,
+        parse_stations, write_lenient, write_tenths,
+    };
+    use std::collections::HashSet;
+
+    /// FNV-1a, to pin the generated bytes without another dependency.
+    fn checksum(bytes: &[u8]) -> u64 {
+        bytes.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &b| {
+            (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01B3)
+        })
+    }
+
+    fn generate(name: &str, rows: usize, seed: u64, block_rows: usize, threads: usize) -> Vec<u8> {
+        write_file(name, &Generator::new(seed, block_rows), rows, threads)
+    }
+
+    fn write_file(name: &str, generator: &Generator, rows: usize, threads: usize) -> Vec<u8> {
+        let path = std::env::temp_dir().join(format!("1brc-test-generate-{name}.txt"));
+        let file = std::fs::File::create(&path).unwrap();
+        let written = generator
+            .write(Sink::File(&file), Target::Rows(rows), threads)
+            .unwrap();
+        drop(file);
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(bytes.len() as u64, written.bytes);
+        assert_eq!(written.rows, rows as u64);
+        bytes
+    }
+
+    #[test]
+    fn test_same_seed_gives_identical_file() {
+        let first = generate("first", 1000, 42, BLOCK_ROWS, 1);
+        let second = generate("second", 1000, 42, BLOCK_ROWS, 4);
+        assert_eq!(first, second);
+
+        let other = generate("other", 1000, 43, BLOCK_ROWS, 1);
+        assert_ne!(first, other);
+
+        assert_eq!(first.iter().filter(|&&b| b == b'\n').count(), 1000);
+        assert_eq!(checksum(&first), 11_118_001_792_441_523_915);
+    }
+
+    #[test]
+    fn test_output_does_not_depend_on_thread_count() {
+        // small blocks, so every thread count splits the rows differently
+        let expected = generate("threads-1", 10_000, 7, 97, 1);
+        assert_eq!(expected.iter().filter(|&&b| b == b'\n').count(), 10_000);
+        for threads in [2, 3, 8, 200] {
+            let name = format!("threads-{threads}");
+            assert_eq!(generate(&name, 10_000, 7, 97, threads), expected);
+        }
+
+        // the first block continues the seed's stream, the rest are sub-seeded
+        let single_block = generate("single-block", 10_000, 7, 10_000, 1);
+        assert_eq!(expected[..200], single_block[..200]);
+        assert_ne!(expected, single_block);
+    }
+
+    #[test]
+    fn test_temperatures_have_one_fractional_digit() {
+        let format = |tenths| {
+            let mut buffer = Vec::new();
+            write_tenths(&mut buffer, tenths);
+            String::from_utf8(buffer).unwrap()
+        };
+        assert_eq!(format(220), "22.0");
+        assert_eq!(format(0), "0.0");
+        assert_eq!(format(-5), "-0.5");
+        assert_eq!(format(-999), "-99.9");
+        assert_eq!(format(999), "99.9");
+
+        let bytes = generate("challenge", 10_000, 1, BLOCK_ROWS, 1);
+        for line in std::str::from_utf8(&bytes).unwrap().lines() {
+            let (_, value) = line.rsplit_once(';').unwrap();
+            let (whole, tenth) = value.trim_start_matches('-').split_once('.').unwrap();
+            assert!((1..=2).contains(&whole.len()) && tenth.len() == 1, "{line}");
+            assert_ne!(value, "-0.0");
+        }
+    }
+
+    #[test]
+    fn test_lenient_format_keeps_values() {
+        let spellings = |tenths| -> Vec<String> {
+            (0..5)
+                .map(|spelling| {
+                    let mut buffer = Vec::new();
+                    write_lenient(&mut buffer, tenths, spelling);
+                    String::from_utf8(buffer).unwrap()
+                })
+                .collect()
+        };
+        assert_eq!(spellings(220), ["22.0", "+22.0", "22.00", "22.0", "22"]);
+        assert_eq!(spellings(-5), ["-0.5", "-0.5", "-0.50", "-.5", "-0.5"]);
+        assert_eq!(spellings(0), ["0.0", "+0.0", "0.00", ".0", "-0"]);
+
+        let challenge = generate("strict", 2000, 1, 97, 1);
+        let lenient = Generator::new(1, 97).with_format(Format::Lenient);
+        let lenient = write_file("lenient", &lenient, 2000, 3);
+
+        let rows = |bytes: &[u8]| -> Vec<(String, f64)> {
+            std::str::from_utf8(bytes)
+                .unwrap()
+                .lines()
+                .map(|line| {
+                    let (city, value) = line.rsplit_once(';').unwrap();
+                    (city.to_string(), value.parse().unwrap())
+                })
+                .collect()
+        };
+        assert_ne!(challenge, lenient);
+        assert_eq!(rows(&challenge), rows(&lenient));
+    }
+
+    #[test]
+    fn test_synthetic_names_are_distinct_utf8() {
+        let generator = Generator::synthetic(3, 97, 10_000, 1..=MAX_NAME_BYTES).unwrap();
+        let names: Vec<_> = generator.stations.iter().map(|(name, ..)| name).collect();
+
+        let distinct: HashSet<_> = names.iter().collect();
+        assert_eq!(distinct.len(), 10_000);
+        assert!(
+            names
+                .iter()
+                .all(|name| (1..=MAX_NAME_BYTES).contains(&name.len()))
+        );
+        assert!(names.iter().all(|name| !name.contains([';', '\n'])));
+        assert!(names.iter().any(|name| name.len() > 16));
+        for width in 2..=4 {
+            assert!(
+                names
+                    .iter()
+                    .any(|name| name.chars().any(|c| c.len_utf8() == width))
+            );
+        }
+
+        // the same seed gives the same stations, and the rows use them all
+        let bytes = write_file("synthetic", &generator, 200_000, 3);
+        let again = Generator::synthetic(3, 97, 10_000, 1..=MAX_NAME_BYTES).unwrap();
+        assert_eq!(bytes, write_file("synthetic-again", &again, 200_000, 1));
+        let seen: HashSet<_> = std::str::from_utf8(&bytes)
+            .unwrap()
+            .lines()
+            .map(|line| line.rsplit_once(';').unwrap().0)
+            .collect();
+        assert_eq!(seen.len(), 10_000);
+    }
+
+    #[test]
+    fn test_synthetic_name_lengths() {
+        let generator = Generator::synthetic(3, 97, 500, 20..=24).unwrap();
+        assert!(
+            generator
+                .stations
+                .iter()
+                .all(|(name, ..)| (20..=24).contains(&name.len()))
+        );
+
+        assert_eq!(parse_lengths("5-40").unwrap(), 5..=40);
+        assert_eq!(parse_lengths("16").unwrap(), 16..=16);
+        assert!(parse_lengths("0-10").is_err());
+        assert!(parse_lengths("10-5").is_err());
+        assert!(parse_lengths("1-101").is_err());
+
+        // only 26 one-byte names exist
+        assert!(Generator::synthetic(3, 97, 27, 1..=1).is_err());
+    }
+
+    /// Writes `rows` rows and checks that parsing them, with the parser and with
+    /// the reference implementation, gives [`Generator::write_expected`].
+    fn assert_parses_to_expected(name: &str, generator: &Generator, rows: usize) {
+        use one_billion_row_challenge::{
+            file,
+            output::{Format as OutputFormat, Writer},
+            reference,
+        };
+
+        let path = std::env::temp_dir().join(format!("1brc-test-generate-{name}.txt"));
+        let output = std::fs::File::create(&path).unwrap();
+        let written = generator
+            .write(Sink::File(&output), Target::Rows(rows), 3)
+            .unwrap();
+        drop(output);
+        // big enough for `chunk_file` to split it
+        let size = written.bytes;
+        assert!(size > 16 * 4096, "{name} wrote only {size} bytes");
+
+        let mut expected = Vec::new();
+        generator
+            .write_expected(&mut expected, &written.totals)
+            .unwrap();
+
+        let parsed = file::File::open(path.to_str().unwrap()).unwrap().parse();
+        let mut actual = Vec::new();
+        Writer::new(OutputFormat::Challenge, false)
+            .write(&mut actual, &parsed)
+            .unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        let oracle = reference::format(&reference::aggregate(&text).unwrap());
+        std::fs::remove_file(path).unwrap();
+
+        assert!(
+            oracle.as_bytes() == expected,
+            "{name}: expected {}, reference gave {oracle}",
+            String::from_utf8_lossy(&expected)
+        );
+        assert!(
+            actual == expected,
+            "{name}: expected {}, got {}",
+            String::from_utf8_lossy(&expected),
+            String::from_utf8_lossy(&actual)
+        );
+    }
+
+    #[test]
+    fn test_profiles_match_their_expected_output() {
+        let profiles = [
+            Profile::Weather,
+            Profile::Hot,
+            Profile::Uniform,
+            Profile::Temperatures,
+            Profile::KeyLengths,
+            Profile::Boundaries,
+        ];
+        for profile in profiles {
+            let generator = Generator::new(11, 997).with_profile(profile);
+            assert_parses_to_expected(&format!("{profile:?}"), &generator, 50_000);
+        }
+    }
+
+    #[test]
+    fn test_most_stations_match_their_expected_output() {
+        // the 10,000 stations the challenge allows, more than the table starts with
+        let generator = Generator::synthetic(5, 997, 10_000, 1..=MAX_NAME_BYTES).unwrap();
+        assert_parses_to_expected("10k-stations", &generator, 200_000);
+    }
+
+    #[test]
+    fn test_edge_profiles_cover_their_cases() {
+        let generator = Generator::new(11, 997).with_profile(Profile::KeyLengths);
+        for len in KEY_LENGTHS {
+            let names = generator
+                .stations
+                .iter()
+                .filter(|(name, ..)| name.len() == len);
+            let (ascii, wide): (Vec<_>, Vec<_>) = names.partition(|(name, ..)| name.is_ascii());
+            assert!(ascii.len() >= 2, "{len} bytes: {ascii:?}");
+            assert_eq!(wide.is_empty(), len == 1, "{len} bytes: {wide:?}");
+        }
+
+        // every temperature, in order
+        let generator = Generator::new(11, 997).with_profile(Profile::Temperatures);
+        let bytes = write_file("temperatures", &generator, 1999, 2);
+        let values: Vec<String> = std::str::from_utf8(&bytes)
+            .unwrap()
+            .lines()
+            .map(|line| line.rsplit_once(';').unwrap().1.to_string())
+            .collect();
+        assert_eq!(values.first().map(String::as_str), Some("-99.9"));
+        assert_eq!(values[999], "0.0");
+        assert_eq!(values.last().map(String::as_str), Some("99.9"));
+
+        // every name length with every temperature width
+        let generator = Generator::new(11, 997).with_profile(Profile::Boundaries);
+        let rows = MAX_NAME_BYTES * BOUNDARY_TENTHS.len();
+        let bytes = write_file("boundaries", &generator, rows, 2);
+        let lengths: HashSet<_> = bytes.split(|&b| b == b'\n').map(<[u8]>::len).collect();
+        assert_eq!(
+            lengths,
+            (0..=106).filter(|&len| len == 0 || len >= 5).collect()
+        );
+    }
+
+    #[test]
+    fn test_station_file() {
+        let contents = "# station;mean;stddev\nHot;30.0;0\r\n\nCold;-10.5\nMixed;1;2.5\n";
+        let stations = parse_stations(contents).unwrap();
+        assert_eq!(
+            stations,
+            [
+                ("Hot".to_string(), 30.0, Some(0.0)),
+                ("Cold".to_string(), -10.5, None),
+                ("Mixed".to_string(), 1.0, Some(2.5)),
+            ]
+        );
+
+        for invalid in [
+            "",
+            "Hot",
+            "Hot;warm",
+            "Hot;1;-2",
+            "Hot;1;2;3",
+            ";1",
+            "Hot;1\nHot;2",
+        ] {
+            assert!(parse_stations(invalid).is_err(), "{invalid:?}");
+        }
+
+        // a station without spread always reports its mean
+        let generator = Generator::with_stations(
+            1,
+            97,
+            vec![("Hot".to_string(), 30.04, Some(0.0))],
+            Xoshiro256StarStar::new(1),
+        );
+        let bytes = write_file("station-file", &generator, 500, 2);
+        assert!(
+            std::str::from_utf8(&bytes)
+                .unwrap()
+                .lines()
+                .all(|line| line == "Hot;30.0")
+        );
+    }
+
+    #[test]
+    fn test_zipf_station_frequency() {
+        assert_eq!("zipf".parse::<Frequency>().unwrap(), Frequency::Zipf(1.0));
+        assert_eq!(
+            "zipf:1.5".parse::<Frequency>().unwrap(),
+            Frequency::Zipf(1.5)
+        );
+        assert!("zipf:0".parse::<Frequency>().is_err());
+        assert!("pareto".parse::<Frequency>().is_err());
+
+        let stations = parse_stations("a;0\nb;0\nc;0\nd;0").unwrap();
+        let generator = Generator::with_stations(1, 997, stations, Xoshiro256StarStar::new(1))
+            .with_frequency(Frequency::Zipf(1.0));
+        let bytes = write_file("zipf", &generator, 100_000, 3);
+
+        let mut counts = [0usize; 4];
+        for line in bytes.split(|&b| b == b'\n').filter(|line| !line.is_empty()) {
+            counts[(line[0] - b'a') as usize] += 1;
+        }
+        // weights 1, 1/2, 1/3, 1/4
+        for (count, expected) in counts.iter().zip([48_000, 24_000, 16_000, 12_000]) {
+            assert!(count.abs_diff(expected) < 1000, "{counts:?}");
+        }
+    }
+
+    #[test]
+    fn test_size_target_writes_whole_rows() {
+        let generator = Generator::new(4, 97);
+        for limit in [0, 5, 100_000, 123_457] {
+            let mut bytes = Vec::new();
+            let written = generator
+                .write(Sink::Stream(&mut bytes), Target::Bytes(limit), 3)
+                .unwrap();
+
+            assert_eq!(bytes.len() as u64, written.bytes);
+            assert!(
+                written.bytes <= limit && written.bytes + 40 > limit,
+                "{limit}: {} bytes",
+                written.bytes
+            );
+            assert!(bytes.is_empty() || bytes.ends_with(b"\n"));
+            let counted: u64 = written.totals.iter().map(|totals| totals.count).sum();
+            assert_eq!(counted, written.rows);
+
+            // the same rows as asking for that many
+            let name = format!("size-{limit}");
+            assert_eq!(
+                bytes,
+                write_file(&name, &generator, written.rows as usize, 2)
+            );
+        }
+    }
+
+    #[test]
+    fn test_gzip_stream() {
+        use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+        use std::io::Read;
+
+        let generator = Generator::new(4, 97);
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+        generator
+            .write(Sink::Stream(&mut encoder), Target::Rows(5000), 3)
+            .unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut bytes = Vec::new();
+        GzDecoder::new(&compressed[..])
+            .read_to_end(&mut bytes)
+            .unwrap();
+        assert!(compressed.len() < bytes.len());
+        assert_eq!(bytes, write_file("gzip", &generator, 5000, 1));
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1024").unwrap(), 1024);
+        assert_eq!(parse_size("13GiB").unwrap(), 13 << 30);
+        assert_eq!(parse_size("13G").unwrap(), 13 << 30);
+        assert_eq!(parse_size("500MB").unwrap(), 500_000_000);
+        assert_eq!(parse_size("2 KiB").unwrap(), 2048);
+        for invalid in ["", "GiB", "1.5GiB", "12 parsecs", "99999999TiB"] {
+            assert!(parse_size(invalid).is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn test_buffering_is_bounded_in_bytes() {
+        let generator = Generator::new(9, 97);
+        // the longest built-in name, `Las Palmas de Gran Canaria`, is 26 bytes
+        assert_eq!(generator.block_bytes(), 97 * 34);
+        assert_eq!(generator.plan(8), (8, BLOCKS_PER_RUN));
+
+        let bounded = |blocks: usize| Generator {
+            max_buffered: blocks * generator.block_bytes(),
+            ..Generator::new(9, 97)
+        };
+        assert_eq!(bounded(6).plan(4), (4, 1));
+        assert_eq!(bounded(2).plan(4), (2, 1));
+        assert_eq!(bounded(0).plan(4), (1, 1));
+
+        // fewer threads and shorter runs give the same file
+        let expected = write_file("unbounded", &generator, 10_000, 4);
+        for blocks in [0, 2, 6] {
+            let name = format!("bounded-{blocks}");
+            assert_eq!(write_file(&name, &bounded(blocks), 10_000, 4), expected);
+        }
+    }
+}
@@ -1,46 +1,193 @@
-//! Generate a file with an arbitrary number of rows.
-//!
-//! Taken from https://github.com/coriolinus/1brc/blob/main/src/bin/generate.rs
-//!
-//! See reference implementation: https://github.com/gunnarmorling/1brc/blob/main/src/main/java/dev/morling/onebrc/CreateMeasurements.java
+use std::{collections::HashSet, ops::RangeInclusive};
 
-use anyhow::{Context, Result, anyhow};
-use rand::prelude::IndexedRandom;
-use rand_distr::{Distribution, Normal};
-use std::io::Write;
+use anyhow::{Result, anyhow, bail};
 
-fn main() -> Result<()> {
-    let n = std::env::args()
-        .nth(1)
-        .ok_or_else(|| anyhow!("requires at least one argument"))?
-        .parse::<usize>()
-        .context("must be able to parse as usize")?;
+use crate::rng::Xoshiro256StarStar;
 
-    let mut rng = rand::rng();
+/// Longest station name the challenge allows, in bytes.
+pub(crate) const MAX_NAME_BYTES: usize = 100;
 
-    let file =
-        std::fs::File::create("measurements.txt").context("opening output file for write")?;
-    let mut buf = std::io::BufWriter::new(file);
+/// Code points synthetic names are drawn from, one range per UTF-8 width,
+/// each with how many times out of 20 a character of that width is picked.
+const NAME_CHARS: [(RangeInclusive<u32>, usize); 4] = [
+    // Latin lowercase
+    (0x61..=0x7A, 12),
+    // Cyrillic lowercase
+    (0x0430..=0x044F, 5),
+    // CJK unified ideographs
+    (0x4E00..=0x9FFF, 2),
+    // pictographs
+    (0x1F300..=0x1F5FF, 1),
+];
+
+/// Characters edge-case names cycle through, one of each UTF-8 width.
+const EDGE_CHARS: [char; 4] = ['a', 'é', '東', '🌍'];
+
+/// Name lengths of the `key-lengths` profile: both sides of every `hash_key`
+/// branch, the word sizes it reads, and the challenge's limit.
+pub(crate) const KEY_LENGTHS: [usize; 16] =
+    [1, 2, 3, 4, 5, 7, 8, 9, 15, 16, 17, 24, 31, 32, 33, 100];
+
+/// Parse `--name-length`: `MIN-MAX` or a single length, in bytes.
+pub(crate) fn parse_lengths(s: &str) -> Result<RangeInclusive<usize>> {
+    let (min, max) = s.split_once('-').unwrap_or((s, s));
+    let parse = |n: &str| n.trim().parse::<usize>().ok();
+    match (parse(min), parse(max)) {
+        (Some(min), Some(max)) if 1 <= min && min <= max && max <= MAX_NAME_BYTES => Ok(min..=max),
+        _ => bail!("--name-length must be `MIN-MAX` with 1 <= MIN <= MAX <= {MAX_NAME_BYTES}: {s}"),
+    }
+}
+
+/// Parse `station;mean[;stddev]` lines, skipping blank lines and `#` comments.
+pub(crate) fn parse_stations(contents: &str) -> Result<Vec<(String, f64, Option<f64>)>> {
+    let mut seen = HashSet::new();
+    let mut stations = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let number = |field: &str, what: &str| {
+            field
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|value| value.is_finite())
+                .ok_or_else(|| anyhow!("line {}: invalid {what}: {field}", i + 1))
+        };
+
+        let fields: Vec<&str> = line.split(';').collect();
+        let (city, mean, std_dev) = match fields[..] {
+            [city, mean] => (city, number(mean, "mean")?, None),
+            [city, mean, std_dev] => (
+                city,
+                number(mean, "mean")?,
+                Some(number(std_dev, "stddev")?),
+            ),
+            _ => bail!("line {}: expected `station;mean[;stddev]`: {line}", i + 1),
+        };
+        if city.is_empty() || city.len() > MAX_NAME_BYTES {
+            bail!(
+                "line {}: station names must have 1 to {MAX_NAME_BYTES} bytes",
+                i + 1
+            );
+        }
+        if std_dev.is_some_and(|std_dev| std_dev < 0.0) {
+            bail!("line {}: stddev can't be negative", i + 1);
+        }
+        if !seen.insert(city) {
+            bail!("line {}: duplicate station {city}", i + 1);
+        }
+        stations.push((city.to_string(), mean, std_dev));
+    }
+
+    if stations.is_empty() {
+        bail!("no stations");
+    }
+    Ok(stations)
+}
+
+/// Draw `count` distinct station names with byte lengths in `lengths`.
+pub(crate) fn synthesize_names(
+    rng: &mut Xoshiro256StarStar,
+    count: usize,
+    lengths: RangeInclusive<usize>,
+) -> Result<Vec<String>> {
+    // short ranges run out of distinct names; give up after this many misses in a row
+    const MAX_MISSES: usize = 10_000;
 
-    let std_devs = Normal::new(10.0, 2.5).context("std_devs is valid normal distribution")?;
-    let mut data = Vec::with_capacity(DATA.len());
-    for (city, mean) in DATA.iter().copied() {
-        let dist = Normal::new(mean, std_devs.sample(&mut rng))
-            .context(anyhow!("creating new normal distribution for {city}"))?;
-        data.push((city, dist));
+    let mut seen = HashSet::with_capacity(count);
+    let mut names = Vec::with_capacity(count);
+    let mut misses = 0;
+    while names.len() < count {
+        let len = lengths.start() + rng.next_below(lengths.end() - lengths.start() + 1);
+        let name = synthesize_name(rng, len);
+        if seen.insert(name.clone()) {
+            names.push(name);
+            misses = 0;
+        } else {
+            misses += 1;
+            if misses == MAX_MISSES {
+                bail!(
+                    "can't find {count} distinct names of {}-{} bytes",
+                    lengths.start(),
+                    lengths.end()
+                );
+            }
+        }
     }
+    Ok(names)
+}
 
-    for _ in 0..n {
-        let (city, dist) = data.choose(&mut rng).expect("data is not empty");
-        let temp = dist.sample(&mut rng);
-        let temp = (temp * 10.0).round() / 10.0;
-        writeln!(buf, "{city};{temp}").context("writing data line")?;
+/// A name of exactly `len` bytes. Characters too wide for the bytes left
+/// are replaced by ASCII ones.
+fn synthesize_name(rng: &mut Xoshiro256StarStar, len: usize) -> String {
+    let total: usize = NAME_CHARS.iter().map(|(_, weight)| weight).sum();
+
+    let mut name = String::with_capacity(len);
+    while name.len() < len {
+        let mut pick = rng.next_below(total);
+        let mut widths = NAME_CHARS.iter();
+        let range = loop {
+            let (range, weight) = widths.next().expect("pick is below the total weight");
+            if pick < *weight {
+                break range;
+            }
+            pick -= weight;
+        };
+        let c = char::from_u32(range.start() + rng.next_below(range.clone().count()) as u32)
+            .expect("NAME_CHARS only holds scalar values");
+
+        if c.len_utf8() <= len - name.len() {
+            name.push(c);
+        } else {
+            name.push((b'a' + rng.next_below(26) as u8) as char);
+        }
     }
+    name
+}
 
-    Ok(())
+/// The `key-lengths` names: for every length, an ASCII name, names differing
+/// from it in the first, middle or last byte only, and multi-byte names.
+pub(crate) fn key_length_names() -> Vec<String> {
+    let mut seen = HashSet::new();
+    let mut names = Vec::new();
+    for len in KEY_LENGTHS {
+        let base: String = (0..len).map(|i| (b'a' + (i % 26) as u8) as char).collect();
+        let mut variants = vec![base.clone()];
+        for at in [0, len / 2, len - 1] {
+            let mut twin = base.clone().into_bytes();
+            twin[at] = b'Z';
+            variants.push(String::from_utf8(twin).expect("ASCII"));
+        }
+        variants.extend((0..EDGE_CHARS.len()).map(|start| edge_name(len, start)));
+
+        names.extend(
+            variants
+                .into_iter()
+                .filter(|name| seen.insert(name.clone())),
+        );
+    }
+    names
+}
+
+/// A name of exactly `len` bytes cycling through [`EDGE_CHARS`] from `start`,
+/// with ASCII where the next character doesn't fit.
+pub(crate) fn edge_name(len: usize, start: usize) -> String {
+    let mut name = String::with_capacity(len);
+    let mut chars = EDGE_CHARS.iter().cycle().skip(start % EDGE_CHARS.len());
+    while name.len() < len {
+        let c = *chars.next().expect("cycle never ends");
+        name.push(if c.len_utf8() <= len - name.len() {
+            c
+        } else {
+            'x'
+        });
+    }
+    name
 }
 
-const DATA: &[(&str, f64)] = &[
+pub(crate) const DATA: &[(&str, f64)] = &[
     ("Abha", 18.0),
     ("Abidjan", 26.0),
     ("Abéché", 29.4),
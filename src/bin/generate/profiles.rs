@@ -0,0 +1,214 @@
+use anyhow::{Result, anyhow, bail};
+
+use crate::rng::Xoshiro256StarStar;
+
+/// Temperatures of the `boundaries` profile, one of every spelled width.
+pub(crate) const BOUNDARY_TENTHS: [i32; 7] = [-999, -99, -5, 0, 5, 99, 999];
+
+/// What the generated rows look like; see the module docs.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum Profile {
+    #[default]
+    Weather,
+    Hot,
+    Uniform,
+    Temperatures,
+    KeyLengths,
+    Boundaries,
+}
+
+impl Profile {
+    /// Whether the profile brings its own station names.
+    pub(crate) fn has_own_stations(self) -> bool {
+        matches!(self, Self::KeyLengths | Self::Boundaries)
+    }
+}
+
+impl std::str::FromStr for Profile {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "weather" => Ok(Self::Weather),
+            "hot" => Ok(Self::Hot),
+            "uniform" => Ok(Self::Uniform),
+            "temperatures" => Ok(Self::Temperatures),
+            "key-lengths" => Ok(Self::KeyLengths),
+            "boundaries" => Ok(Self::Boundaries),
+            _ => bail!(
+                "unknown profile: {s} (expected `weather`, `hot`, `uniform`, `temperatures`, \
+                 `key-lengths` or `boundaries`)"
+            ),
+        }
+    }
+}
+
+/// The distribution temperatures are drawn from around each station's mean.
+/// All of them have the station's stddev.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum Distribution {
+    #[default]
+    Normal,
+    Uniform,
+    /// Two normal peaks at `mean ± 0.9 stddev`, each `sqrt(0.19) stddev` wide.
+    Bimodal,
+}
+
+impl Distribution {
+    /// A draw with mean 0 and standard deviation 1.
+    #[inline(always)]
+    pub(crate) fn sample(self, rng: &mut Xoshiro256StarStar) -> f64 {
+        // the half-width of a uniform distribution with unit variance
+        const SQRT_3: f64 = 1.732_050_807_568_877_2;
+        // the width of bimodal peaks at ±0.9, for unit variance
+        const SQRT_0_19: f64 = 0.435_889_894_354_067_33;
+
+        match self {
+            Self::Normal => rng.next_normal(),
+            Self::Uniform => SQRT_3 * (2.0 * rng.next_f64() - 1.0),
+            Self::Bimodal => {
+                let peak = if rng.next_below(2) == 0 { -0.9 } else { 0.9 };
+                peak + SQRT_0_19 * rng.next_normal()
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for Distribution {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "normal" => Ok(Self::Normal),
+            "uniform" => Ok(Self::Uniform),
+            "bimodal" => Ok(Self::Bimodal),
+            _ => bail!("unknown distribution: {s} (expected `normal`, `uniform` or `bimodal`)"),
+        }
+    }
+}
+
+/// How often each station is picked.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum Frequency {
+    #[default]
+    Uniform,
+    /// The station at rank `k` (from 1) is picked with weight `k^-s`.
+    Zipf(f64),
+}
+
+impl std::str::FromStr for Frequency {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            None if s == "uniform" => Ok(Self::Uniform),
+            None if s == "zipf" => Ok(Self::Zipf(1.0)),
+            Some(("zipf", exponent)) => exponent
+                .parse::<f64>()
+                .ok()
+                .filter(|s| s.is_finite() && *s > 0.0)
+                .map(Self::Zipf)
+                .ok_or_else(|| anyhow!("Zipf exponent must be a positive number: {exponent}")),
+            _ => bail!("unknown station frequency: {s} (expected `uniform` or `zipf[:S]`)"),
+        }
+    }
+}
+
+/// How temperatures are spelled.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum Format {
+    /// Exactly one fractional digit, `-99.9..=99.9`.
+    #[default]
+    Challenge,
+    /// The same values, but every row picks one of several spellings.
+    Lenient,
+}
+
+impl std::str::FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "challenge" => Ok(Self::Challenge),
+            "lenient" => Ok(Self::Lenient),
+            _ => bail!("unknown format: {s} (expected `challenge` or `lenient`)"),
+        }
+    }
+}
+
+/// Write `tenths` as the challenge spells it: optional `-`, then the whole
+/// degrees and exactly one fractional digit. Zero is never negative.
+pub(crate) fn write_tenths(buffer: &mut Vec<u8>, tenths: i32) {
+    if tenths < 0 {
+        buffer.push(b'-');
+    }
+    let (whole, tenth) = (tenths.unsigned_abs() / 10, tenths.unsigned_abs() % 10);
+    if whole >= 10 {
+        buffer.push(b'0' + (whole / 10) as u8);
+    }
+    buffer.extend_from_slice(&[b'0' + (whole % 10) as u8, b'.', b'0' + tenth as u8]);
+}
+
+/// Write `tenths` in one of the alternative spellings lenient parsers accept,
+/// falling back to the challenge spelling where one doesn't apply.
+pub(crate) fn write_lenient(buffer: &mut Vec<u8>, tenths: i32, spelling: usize) {
+    let start = buffer.len();
+    write_tenths(buffer, tenths);
+    match spelling {
+        // `+22.5`
+        1 if tenths >= 0 => buffer.insert(start, b'+'),
+        // `22.50`
+        2 => buffer.push(b'0'),
+        // `.5`, `-.5`
+        3 if tenths.abs() < 10 => {
+            buffer.remove(buffer.len() - 3);
+        }
+        // `-0`
+        4 if tenths == 0 => {
+            buffer.truncate(start);
+            buffer.extend_from_slice(b"-0");
+        }
+        // `22`
+        4 if tenths % 10 == 0 => buffer.truncate(buffer.len() - 2),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Distribution;
+    use crate::rng::Xoshiro256StarStar;
+
+    #[test]
+    fn test_distributions_have_unit_variance() {
+        let mut rng = Xoshiro256StarStar::new(5);
+        let near =
+            |samples: &[f64], x: f64| samples.iter().filter(|s| (*s - x).abs() < 0.2).count();
+
+        for distribution in [
+            Distribution::Normal,
+            Distribution::Uniform,
+            Distribution::Bimodal,
+        ] {
+            let samples: Vec<f64> = (0..200_000)
+                .map(|_| distribution.sample(&mut rng))
+                .collect();
+            let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+            let variance =
+                samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / samples.len() as f64;
+            assert!(mean.abs() < 0.01, "{distribution:?} mean {mean}");
+            assert!(
+                (variance - 1.0).abs() < 0.02,
+                "{distribution:?} variance {variance}"
+            );
+
+            // bimodal values cluster at the peaks, the others at the mean or evenly
+            let (center, peak) = (near(&samples, 0.0), near(&samples, 0.9));
+            match distribution {
+                Distribution::Normal => assert!(center > peak * 5 / 4),
+                Distribution::Uniform => assert!(center.abs_diff(peak) < center / 10),
+                Distribution::Bimodal => assert!(peak > center * 3),
+            }
+        }
+    }
+}
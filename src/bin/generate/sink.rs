@@ -0,0 +1,26 @@
+use std::{fs::File, io::Write};
+
+/// Where the rows go.
+pub(crate) enum Sink<'a> {
+    /// A file, written by all threads at once with `pwrite`.
+    File(&'a File),
+    /// Anything else, written in order.
+    Stream(&'a mut dyn Write),
+}
+
+#[cfg(unix)]
+pub(crate) fn write_at(file: &File, buffer: &[u8], offset: u64) -> std::io::Result<()> {
+    std::os::unix::fs::FileExt::write_all_at(file, buffer, offset)
+}
+
+#[cfg(windows)]
+pub(crate) fn write_at(file: &File, mut buffer: &[u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+
+    while !buffer.is_empty() {
+        let written = file.seek_write(buffer, offset)?;
+        buffer = &buffer[written..];
+        offset += written as u64;
+    }
+    Ok(())
+}
@@ -0,0 +1,413 @@
+use std::{collections::BTreeMap, io::Write};
+
+use crate::{
+    measurement::Aggregate,
+    output::{Format, format_tenths, write_delimited_field, write_rows},
+};
+
+/// Challenge temperatures, `-99.9..=99.9`, get a dense bin each.
+const DENSE_MIN: i32 = -999;
+const DENSE_BINS: usize = 1999;
+
+/// Distinct values a histogram counts in a [`Sparse`] table before it
+/// allocates the dense bins, while the table is under half their size.
+const SPARSE_VALUES: usize = 256;
+
+/// HyperLogLog precision: 4096 registers, ~1.6% standard error on the
+/// distinct line count.
+const PRECISION: u32 = 12;
+const REGISTERS: usize = 1 << PRECISION;
+
+/// Raised registers a sketch keeps in a [`Sparse`] table before it allocates
+/// every register.
+const SPARSE_REGISTERS: usize = 256;
+
+/// Number of most frequent values shown per station.
+pub const TOP_VALUES: usize = 3;
+
+/// Small open-addressing table from bin or register indices to values. Every
+/// chunk keeps diagnostics per station, so a station with few rows in a chunk
+/// keeps a few slots instead of thousands of bins and registers.
+#[derive(Clone, Default)]
+struct Sparse<V> {
+    slots: Vec<(u16, V)>,
+    len: usize,
+}
+
+impl<V: Copy + Default> Sparse<V> {
+    const FREE: u16 = u16::MAX;
+
+    /// The value of `key`, inserted as the default if it is missing.
+    #[inline(always)]
+    fn get_mut(&mut self, key: u16) -> &mut V {
+        if self.len * 2 >= self.slots.len() {
+            self.grow();
+        }
+        let mask = self.slots.len() - 1;
+        let mut i = (key as usize).wrapping_mul(0x9E37) & mask;
+        loop {
+            let slot = self.slots[i].0;
+            if slot == Self::FREE {
+                self.slots[i] = (key, V::default());
+                self.len += 1;
+                return &mut self.slots[i].1;
+            }
+            if slot == key {
+                return &mut self.slots[i].1;
+            }
+            i = (i + 1) & mask;
+        }
+    }
+
+    fn grow(&mut self) {
+        let capacity = (self.slots.len() * 2).max(8);
+        let slots = std::mem::replace(&mut self.slots, vec![(Self::FREE, V::default()); capacity]);
+        self.len = 0;
+        for (key, value) in slots {
+            if key != Self::FREE {
+                *self.get_mut(key) = value;
+            }
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (u16, V)> + '_ {
+        self.slots
+            .iter()
+            .copied()
+            .filter(|&(key, _)| key != Self::FREE)
+    }
+}
+
+/// HyperLogLog sketch of the distinct lines seen. Registers merge by taking
+/// the maximum, so chunks combine in any order. The registers are only all
+/// allocated once a few hundred of them were raised.
+#[derive(Clone, Default)]
+pub struct Sketch {
+    sparse: Sparse<u8>,
+    registers: Vec<u8>,
+}
+
+impl Sketch {
+    #[inline(always)]
+    pub fn add(&mut self, hash: u64) {
+        let index = (hash >> (64 - PRECISION)) as u16;
+        // the sentinel bit caps the rank for an all-zero remainder
+        let rank = ((hash << PRECISION) | (1 << (PRECISION - 1))).leading_zeros() as u8 + 1;
+        self.raise(index, rank);
+    }
+
+    #[inline(always)]
+    fn raise(&mut self, index: u16, rank: u8) {
+        let register = if self.registers.is_empty() {
+            self.sparse.get_mut(index)
+        } else {
+            &mut self.registers[index as usize]
+        };
+        if rank > *register {
+            *register = rank;
+        }
+        if self.sparse.len > SPARSE_REGISTERS {
+            self.densify();
+        }
+    }
+
+    fn densify(&mut self) {
+        self.registers = vec![0; REGISTERS];
+        for (index, rank) in std::mem::take(&mut self.sparse).iter() {
+            self.registers[index as usize] = rank;
+        }
+    }
+
+    /// Estimated number of distinct lines, with linear counting for small sets.
+    pub fn estimate(&self) -> f64 {
+        if self.registers.is_empty() && self.sparse.len == 0 {
+            return 0.0;
+        }
+        let (sum, zeros) = if self.registers.is_empty() {
+            let zeros = REGISTERS - self.sparse.len;
+            let raised: f64 = self
+                .sparse
+                .iter()
+                .map(|(_, r)| 2f64.powi(-(r as i32)))
+                .sum();
+            (raised + zeros as f64, zeros)
+        } else {
+            let sum = self.registers.iter().map(|&r| 2f64.powi(-(r as i32))).sum();
+            (sum, self.registers.iter().filter(|&&r| r == 0).count())
+        };
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let estimate = alpha * m * m / sum;
+
+        if estimate <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            estimate
+        }
+    }
+}
+
+impl Aggregate for Sketch {
+    fn merge(&mut self, other: &Self) {
+        for (index, rank) in other.sparse.iter() {
+            self.raise(index, rank);
+        }
+        if other.registers.is_empty() {
+            return;
+        }
+        if self.registers.is_empty() {
+            self.densify();
+        }
+        for (register, &other) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(other);
+        }
+    }
+}
+
+/// 64-bit hash of a whole line for the [`Sketch`]. The table hash is only 32
+/// bits wide, too narrow to count a billion distinct lines.
+#[inline(always)]
+pub fn hash_line(line: &[u8]) -> u64 {
+    let mut hash = 0x9E37_79B9_7F4A_7C15 ^ line.len() as u64;
+    for chunk in line.chunks(8) {
+        let mut word = [0u8; 8];
+        word[..chunk.len()].copy_from_slice(chunk);
+        hash = (hash ^ u64::from_le_bytes(word)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        hash ^= hash >> 31;
+    }
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    hash ^ (hash >> 31)
+}
+
+/// Per-station value histogram plus a sketch of the station's distinct lines.
+#[derive(Clone, Default)]
+pub struct Diagnostics {
+    pub count: u64,
+    /// Counts per bin until more than [`SPARSE_VALUES`] bins are used.
+    sparse: Sparse<u32>,
+    /// One bin per tenth in the challenge range, allocated once `sparse` is full.
+    bins: Vec<u32>,
+    /// Values outside the challenge range, from wider schemas.
+    other: BTreeMap<i32, u64>,
+    lines: Sketch,
+}
+
+impl Diagnostics {
+    #[inline(always)]
+    pub fn add(&mut self, value: i32, line_hash: u64) {
+        self.count += 1;
+        self.lines.add(line_hash);
+        self.add_value(value, 1);
+    }
+
+    #[inline(always)]
+    fn add_value(&mut self, value: i32, count: u32) {
+        let bin = value.wrapping_sub(DENSE_MIN) as usize;
+        if bin >= DENSE_BINS {
+            *self.other.entry(value).or_default() += count as u64;
+        } else if !self.bins.is_empty() {
+            self.bins[bin] += count;
+        } else {
+            *self.sparse.get_mut(bin as u16) += count;
+            if self.sparse.len > SPARSE_VALUES {
+                self.densify();
+            }
+        }
+    }
+
+    fn densify(&mut self) {
+        self.bins = vec![0; DENSE_BINS];
+        for (bin, count) in std::mem::take(&mut self.sparse).iter() {
+            self.bins[bin as usize] = count;
+        }
+    }
+
+    /// Every distinct value and how often it occurred, in ascending order.
+    pub fn values(&self) -> impl Iterator<Item = (i32, u64)> + '_ {
+        let below = self.other.range(..DENSE_MIN);
+        let above = self.other.range(DENSE_MIN + DENSE_BINS as i32..);
+        let mut sparse: Vec<(u16, u32)> = self.sparse.iter().collect();
+        sparse.sort_unstable();
+        let dense = self
+            .bins
+            .iter()
+            .enumerate()
+            .filter(|&(_, &count)| count > 0)
+            .map(|(bin, &count)| (bin as u16, count))
+            .chain(sparse)
+            .map(|(bin, count)| (bin as i32 + DENSE_MIN, count as u64));
+
+        below
+            .map(|(&value, &count)| (value, count))
+            .chain(dense)
+            .chain(above.map(|(&value, &count)| (value, count)))
+    }
+
+    pub fn distinct_values(&self) -> usize {
+        self.sparse.len + self.bins.iter().filter(|&&count| count > 0).count() + self.other.len()
+    }
+
+    /// The `n` most frequent values, ties broken by the lower value.
+    pub fn most_frequent(&self, n: usize) -> Vec<(i32, u64)> {
+        let mut values: Vec<_> = self.values().collect();
+        values.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        values.truncate(n);
+        values
+    }
+
+    /// Estimated number of distinct lines for this station.
+    #[inline(always)]
+    pub fn distinct_lines(&self) -> f64 {
+        self.lines.estimate().min(self.count as f64)
+    }
+
+    /// Estimated number of lines that repeat an earlier line exactly.
+    #[inline(always)]
+    pub fn duplicates(&self) -> u64 {
+        self.count - self.distinct_lines().round() as u64
+    }
+}
+
+impl Aggregate for Diagnostics {
+    fn merge(&mut self, other: &Self) {
+        self.count += other.count;
+        if !other.bins.is_empty() {
+            if self.bins.is_empty() {
+                self.densify();
+            }
+            for (bin, &count) in self.bins.iter_mut().zip(&other.bins) {
+                *bin += count;
+            }
+        }
+        for (bin, count) in other.sparse.iter() {
+            self.add_value(bin as i32 + DENSE_MIN, count);
+        }
+        for (&value, &count) in &other.other {
+            *self.other.entry(value).or_default() += count;
+        }
+        self.lines.merge(&other.lines);
+    }
+}
+
+/// Write one summary row per station: row count, distinct values, estimated
+/// duplicate lines and the most frequent values. CSV and TSV get a header
+/// row; every other format an aligned table.
+pub fn write_summary(
+    output: &mut impl Write,
+    stations: &[(String, Diagnostics)],
+    format: Format,
+) -> std::io::Result<()> {
+    let header = [
+        "station",
+        "count",
+        "distinct_values",
+        "duplicates_est",
+        "most_frequent",
+    ];
+    let rows: Vec<(&str, Vec<String>)> = stations
+        .iter()
+        .map(|(city, diagnostics)| {
+            let most_frequent: Vec<String> = diagnostics
+                .most_frequent(TOP_VALUES)
+                .into_iter()
+                .map(|(value, count)| format!("{} ({count})", format_tenths(value as i64)))
+                .collect();
+            let cells = vec![
+                diagnostics.count.to_string(),
+                diagnostics.distinct_values().to_string(),
+                diagnostics.duplicates().to_string(),
+                most_frequent.join(" "),
+            ];
+            (city.as_str(), cells)
+        })
+        .collect();
+
+    write_rows(output, &header, &rows, format)
+}
+
+/// Write every station's full histogram as tab-separated `station`, `value`, `count` rows.
+pub fn write_histograms(
+    output: &mut impl Write,
+    stations: &[(String, Diagnostics)],
+) -> std::io::Result<()> {
+    writeln!(output, "station\tvalue\tcount")?;
+    for (city, diagnostics) in stations {
+        for (value, count) in diagnostics.values() {
+            write_delimited_field(output, city, b'\t')?;
+            writeln!(output, "\t{}\t{count}", format_tenths(value as i64))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Diagnostics, Sketch, hash_line};
+    use crate::measurement::Aggregate;
+
+    #[test]
+    fn test_histogram_merges_dense_and_sparse_values() {
+        let mut a = Diagnostics::default();
+        for value in [120, 120, -999, 5000] {
+            a.add(value, hash_line(value.to_string().as_bytes()));
+        }
+        let mut b = Diagnostics::default();
+        for value in [120, -1000, 999] {
+            b.add(value, hash_line(value.to_string().as_bytes()));
+        }
+        a.merge(&b);
+
+        let values: Vec<_> = a.values().collect();
+        assert_eq!(
+            values,
+            [(-1000, 1), (-999, 1), (120, 3), (999, 1), (5000, 1)]
+        );
+        assert_eq!(a.count, 7);
+        assert_eq!(a.distinct_values(), 5);
+        assert_eq!(a.most_frequent(2), [(120, 3), (-1000, 1)]);
+        // the three 120 rows are the same line
+        assert_eq!(a.duplicates(), 2);
+    }
+
+    #[test]
+    fn test_small_chunks_stay_sparse_until_merged() {
+        let rows: Vec<i32> = (0..3000).map(|i| (i * 7919) % 2400 - 1200).collect();
+        let mut whole = Diagnostics::default();
+        let mut merged = Diagnostics::default();
+        for chunk in rows.chunks(100) {
+            let mut part = Diagnostics::default();
+            for &value in chunk {
+                let hash = hash_line(value.to_string().as_bytes());
+                whole.add(value, hash);
+                part.add(value, hash);
+            }
+            assert!(part.bins.is_empty() && part.lines.registers.is_empty());
+            merged.merge(&part);
+        }
+
+        assert!(!whole.bins.is_empty() && !merged.bins.is_empty());
+        assert_eq!(
+            merged.values().collect::<Vec<_>>(),
+            whole.values().collect::<Vec<_>>()
+        );
+        assert_eq!(merged.distinct_values(), 2400);
+        assert_eq!(merged.lines.estimate(), whole.lines.estimate());
+    }
+
+    #[test]
+    fn test_sketch_estimates_distinct_lines() {
+        let mut sketch = Sketch::default();
+        let mut other = Sketch::default();
+        for i in 0..200_000u32 {
+            let line = format!("station {};{}.{}", i % 413, i, i % 10);
+            sketch.add(hash_line(line.as_bytes()));
+            // every line again, as if the feed double-sent it
+            other.add(hash_line(line.as_bytes()));
+        }
+        sketch.merge(&other);
+
+        let error = (sketch.estimate() - 200_000.0).abs() / 200_000.0;
+        assert!(error < 0.05, "estimate off by {:.1}%", error * 100.0);
+    }
+}
@@ -1,17 +1,34 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, btree_map::Entry},
+};
+
+use anyhow::bail;
 use memmap2::Mmap;
 
 use crate::{
     NUM_WORKERS,
+    anomaly::{Anomaly, Limits},
+    audit::{Sighting, Tracked},
+    diagnostics::{Diagnostics, hash_line},
+    filter::NameFilter,
     hashmap::HashMap,
-    measurement::FinalMeasurement,
+    measurement::{Accumulate, Aggregate, FinalMeasurement, Measurement, Tally, finalize},
+    schema::{Schema, parse_value},
+    window::{Series, Window, parse_timestamp},
 };
 
 pub struct File {
     mmap: Mmap,
+    schema: Schema,
 }
 
 impl<'a> File {
     pub fn open(path: &str) -> anyhow::Result<Self> {
+        Self::open_with(path, Schema::default())
+    }
+
+    pub fn open_with(path: &str, schema: Schema) -> anyhow::Result<Self> {
         // Use FILE_FLAG_SEQUENTIAL_SCAN on Windows for optimized readahead
         #[cfg(target_os = "windows")]
         let file = {
@@ -25,7 +42,7 @@ impl<'a> File {
         let file = std::fs::File::options().read(true).open(path)?;
 
         let mmap = unsafe { memmap2::MmapOptions::new().huge(None).map(&file)? };
-        Ok(Self { mmap })
+        Ok(Self { mmap, schema })
     }
 
     /// Parse temperature from raw pointer using SWAR — minimal branches.
@@ -64,15 +81,34 @@ impl<'a> File {
         }
     }
 
-    /// Find byte `needle` starting from `ptr`, scanning up to `max_len` bytes.
+    /// [`File::parse_temp`] for the temperature at `pos` in `data`, returning
+    /// the position after it. The last rows of a chunk are parsed from a
+    /// padded copy, so the 8-byte read never goes past `data`.
+    #[inline(always)]
+    unsafe fn parse_temp_in(data: &[u8], pos: usize) -> (i16, usize) {
+        unsafe {
+            let base = data.as_ptr();
+            if data.len() - pos >= 8 {
+                let (val, next) = Self::parse_temp(base.add(pos));
+                return (val, next.offset_from(base) as usize);
+            }
+
+            let mut tail = [b'\n'; 8];
+            tail[..data.len() - pos].copy_from_slice(&data[pos..]);
+            let (val, next) = Self::parse_temp(tail.as_ptr());
+            (val, pos + next.offset_from(tail.as_ptr()) as usize)
+        }
+    }
+
+    /// Find byte `NEEDLE` starting from `ptr`, scanning up to `max_len` bytes.
     /// Returns offset from ptr, or max_len if not found.
     #[inline(always)]
-    #[cfg(target_arch = "x86_64")]
-    unsafe fn find_byte_simd(ptr: *const u8, max_len: usize, needle: u8) -> usize {
+    #[cfg(all(target_arch = "x86_64", not(miri)))]
+    unsafe fn find_byte_simd<const NEEDLE: u8>(ptr: *const u8, max_len: usize) -> usize {
         use std::arch::x86_64::*;
 
         unsafe {
-            let needle_vec = _mm256_set1_epi8(needle as i8);
+            let needle_vec = _mm256_set1_epi8(NEEDLE as i8);
             let mut offset: usize = 0;
 
             // AVX2 path: 32 bytes at a time
@@ -88,7 +124,7 @@ impl<'a> File {
 
             // Scalar fallback for remaining bytes
             while offset < max_len {
-                if *ptr.add(offset) == needle {
+                if *ptr.add(offset) == NEEDLE {
                     return offset;
                 }
                 offset += 1;
@@ -98,14 +134,14 @@ impl<'a> File {
         max_len
     }
 
-    /// Find byte `needle` using NEON — 16 bytes at a time.
+    /// Find byte `NEEDLE` using NEON — 16 bytes at a time.
     #[inline(always)]
-    #[cfg(target_arch = "aarch64")]
-    unsafe fn find_byte_simd(ptr: *const u8, max_len: usize, needle: u8) -> usize {
+    #[cfg(all(target_arch = "aarch64", not(miri)))]
+    unsafe fn find_byte_simd<const NEEDLE: u8>(ptr: *const u8, max_len: usize) -> usize {
         use std::arch::aarch64::*;
 
         unsafe {
-            let needle_vec = vdupq_n_u8(needle);
+            let needle_vec = vdupq_n_u8(NEEDLE);
             let mut offset: usize = 0;
 
             // NEON path: 16 bytes at a time
@@ -123,7 +159,7 @@ impl<'a> File {
 
             // Scalar fallback
             while offset < max_len {
-                if *ptr.add(offset) == needle {
+                if *ptr.add(offset) == NEEDLE {
                     return offset;
                 }
                 offset += 1;
@@ -133,18 +169,34 @@ impl<'a> File {
         max_len
     }
 
-    #[cfg(target_arch = "x86_64")]
-    #[target_feature(enable = "avx2,bmi1,bmi2")]
-    unsafe fn parse_buffer(data: &'a [u8]) -> HashMap<'a> {
+    /// Scalar [`File::find_byte_simd`] for Miri, which can't run the SIMD intrinsics.
+    #[inline(always)]
+    #[cfg(miri)]
+    unsafe fn find_byte_simd<const NEEDLE: u8>(ptr: *const u8, max_len: usize) -> usize {
+        unsafe {
+            (0..max_len)
+                .find(|&offset| *ptr.add(offset) == NEEDLE)
+                .unwrap_or(max_len)
+        }
+    }
+
+    /// Challenge-format parser: `name;temperature` lines, the temperature
+    /// parsed by [`File::parse_temp`] without any validation. Rows whose
+    /// temperature starts at least 8 bytes before the end are read in place;
+    /// only the last few go through the padded [`File::parse_temp_in`].
+    #[inline(always)]
+    unsafe fn parse_buffer_with<V: Accumulate>(data: &'a [u8]) -> HashMap<'a, V> {
         unsafe {
             let mut result = HashMap::new();
             let mut pos = 0;
             let len = data.len();
             let base = data.as_ptr();
 
-            while pos < len {
-                let offset = Self::find_byte_simd(base.add(pos), len - pos, b';');
-                if offset >= len - pos { break; }
+            // a ';' found before `body` has 8 readable bytes after it
+            let body = len.saturating_sub(8);
+            while pos < body {
+                let offset = Self::find_byte_simd::<b';'>(base.add(pos), body - pos);
+                if offset >= body - pos { break; }
                 let semi = pos + offset;
 
                 let name = std::slice::from_raw_parts(base.add(pos), semi - pos);
@@ -153,58 +205,528 @@ impl<'a> File {
                 let hash = result.prefetch_slot(name);
 
                 // Parse temperature (gives time for prefetch to complete)
-                let (val, next_ptr) = Self::parse_temp(base.add(semi + 1));
+                let (val, next) = Self::parse_temp(base.add(semi + 1));
 
-                pos = next_ptr.offset_from(base) as usize;
+                pos = next.offset_from(base) as usize;
 
                 // Insert with pre-computed hash (slot should be warm now)
-                result.insert_with_hash(name, val, hash);
+                result.insert_with_hash(name, val as i32, hash);
+            }
+
+            while pos < len {
+                let offset = Self::find_byte_simd::<b';'>(base.add(pos), len - pos);
+                if offset >= len - pos { break; }
+                let semi = pos + offset;
+
+                let name = std::slice::from_raw_parts(base.add(pos), semi - pos);
+                let hash = result.prefetch_slot(name);
+                let (val, next) = Self::parse_temp_in(data, semi + 1);
+                pos = next;
+                result.insert_with_hash(name, val as i32, hash);
             }
 
             result
         }
     }
 
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "avx2,bmi1,bmi2")]
+    unsafe fn parse_buffer<V: Accumulate>(data: &'a [u8]) -> HashMap<'a, V> {
+        unsafe { Self::parse_buffer_with(data) }
+    }
+
     #[cfg(target_arch = "aarch64")]
     #[target_feature(enable = "crc,neon")]
-    unsafe fn parse_buffer(data: &'a [u8]) -> HashMap<'a> {
+    unsafe fn parse_buffer<V: Accumulate>(data: &'a [u8]) -> HashMap<'a, V> {
+        unsafe { Self::parse_buffer_with(data) }
+    }
+
+    /// The record loop behind every mode on a non-challenge [`Schema`]: splits
+    /// each line of `data` on `DELIM` into the columns up to the schema's last
+    /// one and hands them to `visit`. The modes differ only in what they do
+    /// with a [`Row`], so line endings and short rows are handled here once.
+    #[inline(always)]
+    unsafe fn visit_rows_with<const DELIM: u8>(
+        data: &'a [u8],
+        schema: &Schema,
+        visit: &mut impl FnMut(Row<'a, '_>),
+    ) {
         unsafe {
-            let mut result = HashMap::new();
             let mut pos = 0;
             let len = data.len();
             let base = data.as_ptr();
+            let mut fields = vec![None; schema.last_column() + 1];
 
             while pos < len {
-                let offset = Self::find_byte_simd(base.add(pos), len - pos, b';');
-                if offset >= len - pos { break; }
-                let semi = pos + offset;
+                let offset = pos;
+                let line_len = Self::find_byte_simd::<b'\n'>(base.add(pos), len - pos);
+                let mut line = std::slice::from_raw_parts(base.add(pos), line_len);
+                pos += line_len + 1;
+                if let [rest @ .., b'\r'] = line {
+                    line = rest;
+                }
 
-                let name = std::slice::from_raw_parts(base.add(pos), semi - pos);
+                let mut start = 0;
+                for field in &mut fields {
+                    if start > line.len() {
+                        *field = None;
+                        continue;
+                    }
+                    let field_len =
+                        Self::find_byte_simd::<DELIM>(line.as_ptr().add(start), line.len() - start);
+                    *field = Some(&line[start..start + field_len]);
+                    start += field_len + 1;
+                }
 
-                // Prefetch hash table slot while we parse the temperature
-                let hash = result.prefetch_slot(name);
+                visit(Row {
+                    offset,
+                    line,
+                    fields: &fields,
+                    value: fields[schema.value_columns[0]].and_then(parse_value),
+                });
+            }
+        }
+    }
 
-                // Parse temperature (gives time for prefetch to complete)
-                let (val, next_ptr) = Self::parse_temp(base.add(semi + 1));
+    /// [`File::visit_rows_with`] for challenge input, finding the row ends
+    /// with [`File::parse_temp`] like [`File::parse_buffer`] does.
+    #[inline(always)]
+    unsafe fn visit_challenge_rows_with(data: &'a [u8], visit: &mut impl FnMut(Row<'a, '_>)) {
+        unsafe {
+            let mut pos = 0;
+            let len = data.len();
+            let base = data.as_ptr();
 
-                pos = next_ptr.offset_from(base) as usize;
+            while pos < len {
+                let offset = Self::find_byte_simd::<b';'>(base.add(pos), len - pos);
+                if offset >= len - pos {
+                    break;
+                }
+                let semi = pos + offset;
+                let (value, next) = Self::parse_temp_in(data, semi + 1);
+                let end = (next - 1).min(len);
+                let slice =
+                    |from: usize, to: usize| std::slice::from_raw_parts(base.add(from), to - from);
 
-                // Insert with pre-computed hash (slot should be warm now)
-                result.insert_with_hash(name, val, hash);
+                visit(Row {
+                    offset: pos,
+                    line: slice(pos, end),
+                    fields: &[Some(slice(pos, semi)), Some(slice(semi + 1, end))],
+                    value: Some(value as i32),
+                });
+                pos = next;
             }
+        }
+    }
 
-            result
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "avx2,bmi1,bmi2")]
+    unsafe fn visit_rows_simd<const DELIM: u8>(
+        data: &'a [u8],
+        schema: &Schema,
+        visit: &mut impl FnMut(Row<'a, '_>),
+    ) {
+        unsafe {
+            if DELIM == b';' && schema.is_challenge() {
+                Self::visit_challenge_rows_with(data, visit)
+            } else {
+                Self::visit_rows_with::<DELIM>(data, schema, visit)
+            }
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    #[target_feature(enable = "crc,neon")]
+    unsafe fn visit_rows_simd<const DELIM: u8>(
+        data: &'a [u8],
+        schema: &Schema,
+        visit: &mut impl FnMut(Row<'a, '_>),
+    ) {
+        unsafe {
+            if DELIM == b';' && schema.is_challenge() {
+                Self::visit_challenge_rows_with(data, visit)
+            } else {
+                Self::visit_rows_with::<DELIM>(data, schema, visit)
+            }
+        }
+    }
+
+    /// Runs `visit` on every row of `data`, with [`File::visit_challenge_rows_with`]
+    /// for challenge input and otherwise [`File::visit_rows_with`] monomorphized
+    /// per delimiter, so the SIMD scan compares against a constant. Every mode
+    /// but [`File::parse_buffer`] reads its rows through here, with its
+    /// closure marked `#[inline(always)]` so it is compiled into the loop
+    /// rather than called once per row.
+    unsafe fn visit_rows(data: &'a [u8], schema: &Schema, mut visit: impl FnMut(Row<'a, '_>)) {
+        unsafe {
+            match schema.delimiter {
+                b';' => Self::visit_rows_simd::<b';'>(data, schema, &mut visit),
+                b',' => Self::visit_rows_simd::<b','>(data, schema, &mut visit),
+                b'\t' => Self::visit_rows_simd::<b'\t'>(data, schema, &mut visit),
+                b'|' => Self::visit_rows_simd::<b'|'>(data, schema, &mut visit),
+                b' ' => Self::visit_rows_simd::<b' '>(data, schema, &mut visit),
+                b':' => Self::visit_rows_simd::<b':'>(data, schema, &mut visit),
+                _ => unreachable!("Schema::new only accepts schema::DELIMITERS"),
+            }
+        }
+    }
+
+    /// Pick the parser for the schema: the SWAR kernel for challenge input,
+    /// otherwise the first value column of every row with a parseable value.
+    unsafe fn parse_chunk<V: Accumulate>(data: &'a [u8], schema: &Schema) -> HashMap<'a, V> {
+        if schema.is_challenge() {
+            return unsafe { Self::parse_buffer(data) };
+        }
+
+        let mut result = HashMap::new();
+        unsafe {
+            Self::visit_rows(
+                data,
+                schema,
+                #[inline(always)]
+                |row| {
+                    let (Some(key), Some(value)) = (row.key(schema), row.value) else {
+                        return;
+                    };
+                    let hash = result.prefetch_slot(key);
+                    result.insert_with_hash(key, value, hash);
+                },
+            );
+        }
+        result
+    }
+
+    /// Every value column of the schema, in order, into one [`Measurement`]
+    /// per column. Empty or unparseable fields are skipped without dropping
+    /// the rest of the row.
+    unsafe fn parse_multi_chunk(data: &'a [u8], schema: &Schema) -> HashMap<'a, Vec<Measurement>> {
+        let mut result: HashMap<'a, Vec<Measurement>> = HashMap::new();
+        let columns = schema.value_columns();
+        let mut values = vec![None; columns.len()];
+        unsafe {
+            Self::visit_rows(
+                data,
+                schema,
+                #[inline(always)]
+                |row| {
+                    let Some(key) = row.key(schema) else {
+                        return;
+                    };
+                    values[0] = row.value;
+                    for (value, &column) in values.iter_mut().zip(columns).skip(1) {
+                        *value = row.fields[column].and_then(parse_value);
+                    }
+                    if values.iter().all(Option::is_none) {
+                        return;
+                    }
+
+                    let hash = result.prefetch_slot(key);
+                    let measurements = result.entry_with_hash(key, hash);
+                    if measurements.is_empty() {
+                        measurements.resize_with(columns.len(), Measurement::empty);
+                    }
+                    for (measurement, value) in measurements.iter_mut().zip(&values) {
+                        if let Some(value) = *value {
+                            measurement.add(value);
+                        }
+                    }
+                },
+            );
+        }
+        result
+    }
+
+    /// Keys every value by the `window` its timestamp falls in as well. Rows
+    /// without a valid timestamp are skipped.
+    unsafe fn parse_windowed_chunk(
+        data: &'a [u8],
+        schema: &Schema,
+        window: Window,
+    ) -> HashMap<'a, Series> {
+        let mut result: HashMap<'a, Series> = HashMap::new();
+        unsafe {
+            Self::visit_rows(
+                data,
+                schema,
+                #[inline(always)]
+                |row| {
+                    let (Some(key), Some(value), Some(timestamp)) =
+                        (row.key(schema), row.value, row.timestamp(schema))
+                    else {
+                        return;
+                    };
+                    let hash = result.prefetch_slot(key);
+                    result
+                        .entry_with_hash(key, hash)
+                        .entry(window.start(timestamp))
+                        .or_insert_with(Measurement::empty)
+                        .add(value);
+                },
+            );
         }
+        result
+    }
+
+    /// Records where each station was first and last seen as well. `offset`
+    /// is the position of `data` in the file.
+    unsafe fn parse_tracked_chunk(
+        data: &'a [u8],
+        offset: u64,
+        schema: &Schema,
+    ) -> HashMap<'a, Tracked> {
+        let mut result: HashMap<'a, Tracked> = HashMap::new();
+        unsafe {
+            Self::visit_rows(
+                data,
+                schema,
+                #[inline(always)]
+                |row| {
+                    let (Some(key), Some(value)) = (row.key(schema), row.value) else {
+                        return;
+                    };
+                    let hash = result.prefetch_slot(key);
+                    result.entry_with_hash(key, hash).add(Sighting {
+                        offset: offset + row.offset as u64,
+                        value,
+                        timestamp: row.timestamp(schema),
+                    });
+                },
+            );
+        }
+        result
+    }
+
+    /// Anomaly pass over a chunk. Returns the number of lines in `data` and
+    /// the rows `limits` rejects, numbered from the chunk start.
+    unsafe fn scan_chunk(
+        data: &'a [u8],
+        offset: u64,
+        schema: &Schema,
+        limits: &HashMap<'_, Limits>,
+    ) -> (u64, Vec<Anomaly>) {
+        let mut anomalies = Vec::new();
+        let mut lines = 0;
+        unsafe {
+            Self::visit_rows(
+                data,
+                schema,
+                #[inline(always)]
+                |row| {
+                    lines += 1;
+                    let (Some(key), Some(value)) = (row.key(schema), row.value) else {
+                        return;
+                    };
+                    let hash = limits.prefetch_slot(key);
+                    if let Some(station) = limits.get_with_hash(key, hash)
+                        && !station.accepts(value)
+                    {
+                        anomalies.push(Anomaly {
+                            offset: offset + row.offset as u64,
+                            line: lines,
+                            station: String::from_utf8_lossy(key).into_owned(),
+                            value,
+                            limits: *station,
+                            row: String::from_utf8_lossy(row.line).into_owned(),
+                        });
+                    }
+                },
+            );
+        }
+        (lines, anomalies)
+    }
+
+    /// Diagnostics pass over a chunk: a value histogram and a sketch of the
+    /// distinct lines per station.
+    unsafe fn parse_diagnostic_chunk(data: &'a [u8], schema: &Schema) -> HashMap<'a, Diagnostics> {
+        let mut result: HashMap<'a, Diagnostics> = HashMap::new();
+        unsafe {
+            Self::visit_rows(
+                data,
+                schema,
+                #[inline(always)]
+                |row| {
+                    let (Some(key), Some(value)) = (row.key(schema), row.value) else {
+                        return;
+                    };
+                    let hash = result.prefetch_slot(key);
+                    result
+                        .entry_with_hash(key, hash)
+                        .add(value, hash_line(row.line));
+                },
+            );
+        }
+        result
     }
 
     pub fn parse(&self) -> Vec<(String, FinalMeasurement)> {
+        self.parse_filtered(&NameFilter::default())
+    }
+
+    /// Like [`File::parse`], but only keeps stations matching `filter`. The filter runs
+    /// once per distinct station after the chunk maps are merged, never per row.
+    pub fn parse_filtered(&self, filter: &NameFilter) -> Vec<(String, FinalMeasurement)> {
+        finalize(self.measurements(filter))
+    }
+
+    /// The merged per-station aggregates, unsorted, before conversion to
+    /// [`FinalMeasurement`]s, so callers can still combine them. Rows are
+    /// tallied without their squares, so the standard deviation is NaN; use
+    /// [`File::measurements_with_stddev`] when it is needed.
+    pub fn measurements(&self, filter: &NameFilter) -> Vec<(String, Measurement)> {
+        let schema = &self.schema;
+        self.aggregate(filter, |chunk| unsafe {
+            Self::parse_chunk::<Tally>(chunk, schema)
+        })
+        .into_iter()
+        .map(|(city, tally)| (city, tally.into()))
+        .collect()
+    }
+
+    /// Like [`File::measurements`], but also sums the squares of the values
+    /// for the standard deviation, at some cost per row.
+    pub fn measurements_with_stddev(&self, filter: &NameFilter) -> Vec<(String, Measurement)> {
+        let schema = &self.schema;
+        self.aggregate(filter, |chunk| unsafe { Self::parse_chunk(chunk, schema) })
+    }
+
+    /// One aggregate per value column of the schema, in the order the columns
+    /// were given. Columns a station never had a value for stay empty.
+    pub fn multi_measurements(&self, filter: &NameFilter) -> Vec<(String, Vec<Measurement>)> {
+        let schema = &self.schema;
+        self.aggregate(filter, |chunk| unsafe {
+            Self::parse_multi_chunk(chunk, schema)
+        })
+    }
+
+    /// Per-station time series of the value column, bucketed by `window`. The
+    /// schema must have a timestamp column.
+    pub fn windowed_measurements(
+        &self,
+        filter: &NameFilter,
+        window: Window,
+    ) -> Vec<(String, Series)> {
+        let schema = &self.schema;
+        assert!(
+            schema.timestamp_column().is_some(),
+            "windowed aggregation needs a timestamp column"
+        );
+        self.aggregate(filter, |chunk| unsafe {
+            Self::parse_windowed_chunk(chunk, schema, window)
+        })
+    }
+
+    /// Per-station aggregates of the first value column that also record the
+    /// first and last row each station was seen on.
+    pub fn tracked_measurements(&self, filter: &NameFilter) -> Vec<(String, Tracked)> {
+        let schema = &self.schema;
+        let base = self.mmap.as_ptr() as usize;
+        self.aggregate(filter, |chunk| unsafe {
+            let offset = (chunk.as_ptr() as usize - base) as u64;
+            Self::parse_tracked_chunk(chunk, offset, schema)
+        })
+    }
+
+    /// Per-station value histograms and duplicate line estimates of the first
+    /// value column, from the same single pass as the aggregation.
+    pub fn diagnostics(&self, filter: &NameFilter) -> Vec<(String, Diagnostics)> {
+        let schema = &self.schema;
+        self.aggregate(filter, |chunk| unsafe {
+            Self::parse_diagnostic_chunk(chunk, schema)
+        })
+    }
+
+    /// Second pass over the same chunks as the aggregation, in parallel: every
+    /// row of a station in `limits` whose value the station's [`Limits`]
+    /// reject, in file order. Stations missing from `limits` are skipped.
+    pub fn anomalies(&self, limits: &[(String, Limits)]) -> Vec<Anomaly> {
+        let mut table: HashMap<'_, Limits> = HashMap::new();
+        for (station, station_limits) in limits {
+            let hash = table.prefetch_slot(station.as_bytes());
+            *table.entry_with_hash(station.as_bytes(), hash) = *station_limits;
+        }
+
         let chunks = self.chunk_file();
+        let schema = &self.schema;
+        let (base, table) = (self.mmap.as_ptr() as usize, &table);
+        let chunk_results: Vec<(u64, Vec<Anomaly>)> = std::thread::scope(|s| {
+            let handles: Vec<_> = chunks
+                .into_iter()
+                .map(|chunk| {
+                    s.spawn(move || unsafe {
+                        let offset = (chunk.as_ptr() as usize - base) as u64;
+                        Self::scan_chunk(chunk, offset, schema, table)
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        // chunks come back in file order, so shifting each chunk's line numbers
+        // by the lines before it gives file line numbers
+        let mut lines_before = schema.skip_header as u64;
+        let mut anomalies = Vec::new();
+        for (lines, mut chunk_anomalies) in chunk_results {
+            for anomaly in &mut chunk_anomalies {
+                anomaly.line += lines_before;
+            }
+            lines_before += lines;
+            anomalies.append(&mut chunk_anomalies);
+        }
+        anomalies
+    }
+
+    /// 1-based line numbers of the rows starting at `offsets`, in one pass
+    /// over the file up to the last of them.
+    pub fn line_numbers(&self, offsets: impl IntoIterator<Item = u64>) -> BTreeMap<u64, u64> {
+        let mut lines: BTreeMap<u64, u64> = offsets.into_iter().map(|o| (o, 0)).collect();
+
+        let (mut line, mut pos) = (1, 0);
+        for (&offset, number) in &mut lines {
+            let end = (offset as usize).min(self.mmap.len());
+            line += self.mmap[pos..end].iter().filter(|&&b| b == b'\n').count() as u64;
+            pos = end;
+            *number = line;
+        }
+        lines
+    }
+
+    /// Display names for the value columns: the header fields when the schema
+    /// skips a header line, `column N` otherwise.
+    pub fn metric_names(&self) -> Vec<String> {
+        let mut header = Vec::new();
+        if self.schema.skip_header {
+            let line = self.mmap.split(|&b| b == b'\n').next().unwrap_or_default();
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            header = line
+                .split(|&b| b == self.schema.delimiter)
+                .map(|field| String::from_utf8_lossy(field).trim().to_string())
+                .collect();
+        }
+
+        self.schema
+            .value_columns()
+            .iter()
+            .map(|&column| match header.get(column) {
+                Some(name) if !name.is_empty() => name.clone(),
+                _ => format!("column {column}"),
+            })
+            .collect()
+    }
+
+    /// Parse every chunk on its own thread with `parse`, then merge the chunk maps.
+    fn aggregate<V: Aggregate + Send>(
+        &'a self,
+        filter: &NameFilter,
+        parse: impl Fn(&'a [u8]) -> HashMap<'a, V> + Sync,
+    ) -> Vec<(String, V)> {
+        let chunks = self.chunk_file();
+        let parse = &parse;
 
         // Process chunks in parallel using std::thread::scope
-        let chunk_results: Vec<HashMap<'_>> = std::thread::scope(|s| {
+        let chunk_results: Vec<HashMap<'_, V>> = std::thread::scope(|s| {
             let handles: Vec<_> = chunks
                 .into_iter()
-                .map(|chunk| s.spawn(move || unsafe { Self::parse_buffer(chunk) }))
+                .map(|chunk| s.spawn(move || parse(chunk)))
                 .collect();
             handles.into_iter().map(|h| h.join().unwrap()).collect()
         });
@@ -216,55 +738,482 @@ impl<'a> File {
             measurements.merge(chunk_map);
         }
 
-        let mut results: Vec<(String, FinalMeasurement)> = measurements
-            .into_iter()
-            .map(|(city, measurement)| {
-                let final_measurement: FinalMeasurement = measurement.into();
-                let city = unsafe { std::str::from_utf8_unchecked(city) }.to_string();
-                (city, final_measurement)
-            })
-            .collect();
-
-        results.sort_unstable_by(|a, b| a.0.cmp(&b.0));
-        results
+        // Challenge station names are UTF-8 by the rules of the challenge. Keys
+        // of other schemas may not be, so they are converted lossily, and keys
+        // that only differ in their invalid bytes merge into one station.
+        let challenge = self.schema.is_challenge();
+        let mut stations = Vec::with_capacity(measurements.len);
+        let mut replaced: BTreeMap<String, usize> = BTreeMap::new();
+        for (city, measurement) in measurements.into_iter() {
+            let city = if challenge {
+                Cow::Borrowed(unsafe { std::str::from_utf8_unchecked(city) })
+            } else {
+                String::from_utf8_lossy(city)
+            };
+            if !filter.is_empty() && !filter.matches(&city) {
+                continue;
+            }
+            match city {
+                Cow::Borrowed(city) => stations.push((city.to_string(), measurement)),
+                Cow::Owned(city) => match replaced.entry(city) {
+                    Entry::Occupied(index) => stations[*index.get()].1.merge(&measurement),
+                    Entry::Vacant(index) => {
+                        stations.push((index.key().clone(), measurement));
+                        index.insert(stations.len() - 1);
+                    }
+                },
+            }
+        }
+        stations
     }
 
     fn chunk_file(&self) -> Vec<&[u8]> {
-        let buffer = &self.mmap[..];
-        let total_size = buffer.len();
-
-        if total_size == 0 {
+        let mut buffer = &self.mmap[..];
+        if self.schema.skip_header {
+            let header = buffer
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(buffer.len(), |i| i + 1);
+            buffer = &buffer[header..];
+        }
+        if buffer.is_empty() {
             return vec![];
         }
 
-        let num_chunks = NUM_WORKERS;
-        let chunk_size = total_size / num_chunks;
-
         // For very small files, don't bother with multiple chunks
-        if chunk_size < 4096 {
+        if buffer.len() / NUM_WORKERS < 4096 {
             return vec![buffer];
         }
 
-        let mut chunks = Vec::with_capacity(num_chunks + 1);
-        let mut start = 0;
+        split_lines(buffer, NUM_WORKERS)
+    }
+}
+
+/// One line of a non-challenge chunk, split into the columns the schema reads.
+struct Row<'a, 'r> {
+    /// Position of the line in its chunk.
+    offset: usize,
+    /// The line without its line ending.
+    line: &'a [u8],
+    /// Columns `0..=schema.last_column()`, `None` past the end of a short line.
+    fields: &'r [Option<&'a [u8]>],
+    /// The first value column, parsed.
+    value: Option<i32>,
+}
+
+impl<'a> Row<'a, '_> {
+    #[inline(always)]
+    fn key(&self, schema: &Schema) -> Option<&'a [u8]> {
+        self.fields[schema.key_column]
+    }
+
+    #[inline(always)]
+    fn timestamp(&self, schema: &Schema) -> Option<i64> {
+        self.fields[schema.timestamp_column?].and_then(parse_timestamp)
+    }
+}
+
+/// Splits `buffer` into about `num_chunks` chunks that each end just after a
+/// newline (or at the end of `buffer`), so no row straddles two chunks.
+fn split_lines(buffer: &[u8], num_chunks: usize) -> Vec<&[u8]> {
+    let total_size = buffer.len();
+    let chunk_size = total_size / num_chunks;
+
+    let mut chunks = Vec::with_capacity(num_chunks + 1);
+    let mut start = 0;
 
-        while start < total_size {
-            let mut end = (start + chunk_size).min(total_size);
+    while start < total_size {
+        let mut end = (start + chunk_size).min(total_size);
 
+        if end < total_size {
+            // Find next newline to align chunk boundary
+            while end < total_size && buffer[end] != b'\n' {
+                end += 1;
+            }
             if end < total_size {
-                // Find next newline to align chunk boundary
-                while end < total_size && buffer[end] != b'\n' {
-                    end += 1;
-                }
-                if end < total_size {
-                    end += 1; // include the newline
-                }
+                end += 1; // include the newline
             }
+        }
 
-            chunks.push(&buffer[start..end]);
-            start = end;
+        chunks.push(&buffer[start..end]);
+        start = end;
+    }
+
+    chunks
+}
+
+/// Parses `data` as one chunk of challenge input with the kernel [`File::parse`]
+/// runs on every chunk, for fuzzing and differential tests.
+///
+/// With `validate`, `data` must first be strict challenge input and the error
+/// names the first line that is not. Without it, malformed rows give
+/// meaningless aggregates but are still never read past the end of `data`.
+pub fn parse_challenge_chunk(
+    data: &[u8],
+    validate: bool,
+) -> anyhow::Result<Vec<(String, FinalMeasurement)>> {
+    if validate {
+        validate_challenge(data)?;
+    }
+
+    #[cfg(not(miri))]
+    let measurements = unsafe { File::parse_buffer::<Tally>(data) };
+    #[cfg(miri)]
+    let measurements = unsafe { File::parse_buffer_with::<Tally>(data) };
+
+    Ok(finalize(
+        measurements
+            .into_iter()
+            .map(|(city, tally)| (String::from_utf8_lossy(city).into_owned(), tally.into()))
+            .collect(),
+    ))
+}
+
+/// Checks that every line of `data` is `name;temperature` with a name of 1 to
+/// 100 bytes of UTF-8 and a temperature of one or two digits and one decimal.
+fn validate_challenge(data: &[u8]) -> anyhow::Result<()> {
+    for (index, line) in data.split_inclusive(|&b| b == b'\n').enumerate() {
+        let line_number = index + 1;
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let Some(semi) = line.iter().position(|&b| b == b';') else {
+            bail!("line {line_number}: missing ';'");
+        };
+
+        let (name, temperature) = (&line[..semi], &line[semi + 1..]);
+        if name.is_empty() || name.len() > 100 || std::str::from_utf8(name).is_err() {
+            bail!("line {line_number}: station names must be 1 to 100 bytes of UTF-8");
         }
+        let digits = temperature.strip_prefix(b"-").unwrap_or(temperature);
+        let valid = match digits {
+            [a, b'.', b] => a.is_ascii_digit() && b.is_ascii_digit(),
+            [a, b, b'.', c] => a.is_ascii_digit() && b.is_ascii_digit() && c.is_ascii_digit(),
+            _ => false,
+        };
+        if !valid {
+            bail!(
+                "line {line_number}: invalid temperature {:?}",
+                String::from_utf8_lossy(temperature)
+            );
+        }
+    }
 
-        chunks
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::{File, parse_challenge_chunk, split_lines};
+    use crate::{
+        anomaly::Thresholds,
+        filter::NameFilter,
+        measurement::FinalMeasurement,
+        schema::Schema,
+        window::{Window, format_timestamp},
+    };
+
+    #[test]
+    fn test_multi_measurements_keep_columns_apart() {
+        let path = std::env::temp_dir().join("1brc-test-multi.csv");
+        std::fs::write(
+            &path,
+            "station,temp,humidity,pressure\r\nBerlin,10.5,60,1013.2\r\nBerlin,12,,1010\r\nParis,15,70,x\r\n",
+        )
+        .unwrap();
+        let schema = Schema::new(b',', 0, vec![1, 2, 3], true).unwrap();
+        let file = File::open_with(path.to_str().unwrap(), schema).unwrap();
+
+        assert_eq!(file.metric_names(), ["temp", "humidity", "pressure"]);
+
+        let mut stations = file.multi_measurements(&NameFilter::default());
+        stations.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        std::fs::remove_file(path).unwrap();
+
+        let (city, berlin) = &stations[0];
+        assert_eq!(city, "Berlin");
+        let counts: Vec<_> = berlin.iter().map(|m| m.count).collect();
+        assert_eq!(counts, [2, 1, 2]);
+        let pressure = FinalMeasurement::new(berlin[2]);
+        assert_eq!((pressure.min, pressure.max), (1010.0, 1013.2));
+
+        let (city, paris) = &stations[1];
+        assert_eq!(city, "Paris");
+        let counts: Vec<_> = paris.iter().map(|m| m.count).collect();
+        assert_eq!(counts, [1, 1, 0]);
+    }
+
+    #[test]
+    fn test_keys_that_are_not_utf8_are_replaced() {
+        let path = std::env::temp_dir().join("1brc-test-not-utf8.csv");
+        let mut contents = b"S\xE3o Paulo,10\nS\xFFo Paulo,20\nLima,5\n".to_vec();
+        contents.extend_from_slice("Säo Paulo,1\n".as_bytes());
+        std::fs::write(&path, contents).unwrap();
+        let schema = Schema::new(b',', 0, vec![1], false).unwrap();
+        let file = File::open_with(path.to_str().unwrap(), schema).unwrap();
+
+        let mut stations = file.parse();
+        stations.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        std::fs::remove_file(path).unwrap();
+
+        let counts: Vec<_> = stations
+            .iter()
+            .map(|(city, measurement)| (city.as_str(), measurement.count))
+            .collect();
+        assert_eq!(
+            counts,
+            [("Lima", 1), ("Säo Paulo", 1), ("S\u{FFFD}o Paulo", 2)]
+        );
+    }
+
+    #[test]
+    fn test_windows_merge_across_chunks() {
+        // big enough to be split between all the workers
+        let mut contents = String::new();
+        for minute in 0..6000 {
+            let city = ["Berlin", "Paris", "Lima"][minute % 3];
+            let value = minute % 100;
+            contents.push_str(&format!("{};{city};{value}\n", 1_700_000_000 + minute * 60));
+        }
+        let path = std::env::temp_dir().join("1brc-test-windows.txt");
+        std::fs::write(&path, contents).unwrap();
+        let schema = Schema::new(b';', 1, vec![2], false)
+            .unwrap()
+            .with_timestamp_column(0)
+            .unwrap();
+        let file = File::open_with(path.to_str().unwrap(), schema).unwrap();
+        let mut stations = file.windowed_measurements(&NameFilter::default(), Window::DAILY);
+        std::fs::remove_file(path).unwrap();
+
+        stations.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        let names: Vec<_> = stations.iter().map(|(city, _)| city.as_str()).collect();
+        assert_eq!(names, ["Berlin", "Lima", "Paris"]);
+
+        let (_, berlin) = &stations[0];
+        let windows: Vec<_> = berlin
+            .keys()
+            .map(|start| format_timestamp(*start))
+            .collect();
+        assert_eq!(
+            windows,
+            [
+                "2023-11-14T00:00:00Z",
+                "2023-11-15T00:00:00Z",
+                "2023-11-16T00:00:00Z",
+                "2023-11-17T00:00:00Z",
+                "2023-11-18T00:00:00Z",
+                "2023-11-19T00:00:00Z"
+            ]
+        );
+        assert_eq!(berlin.values().map(|m| m.count).sum::<usize>(), 2000);
+
+        // 2023-11-14T22:13:20Z leaves 107 minutes of the first day, every third one Berlin's
+        let first = FinalMeasurement::new(berlin[&1_699_920_000]);
+        assert_eq!((first.count, first.min, first.max), (36, 0.0, 99.0));
+    }
+
+    #[test]
+    fn test_modes_agree_across_layouts() {
+        // a header, CRLF line endings and a short row only shift the line numbers
+        let challenge = "Lima;12.5\nOslo;-3.0\nLima;40.0\nOslo;-3.0\nLima;12.5\n";
+        let csv = "value,station\r\n12.5,Lima\r\n-3.0,Oslo\r\n40.0,Lima\r\nbad\r\n-3.0,Oslo\r\n12.5,Lima\r\n";
+        let layouts = [
+            (
+                "challenge",
+                challenge,
+                Schema::default(),
+                "Lima;40.0",
+                [3, 1, 5],
+            ),
+            (
+                "csv",
+                csv,
+                Schema::new(b',', 1, vec![0], true).unwrap(),
+                "40.0,Lima",
+                [4, 2, 7],
+            ),
+        ];
+
+        for (name, contents, schema, flagged_row, [flagged_line, first_line, last_line]) in layouts
+        {
+            let path = std::env::temp_dir().join(format!("1brc-test-layout-{name}.txt"));
+            std::fs::write(&path, contents).unwrap();
+            let file = File::open_with(path.to_str().unwrap(), schema).unwrap();
+            let filter = NameFilter::default();
+
+            let mut stations = file.measurements(&filter);
+            stations.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+            let counts: Vec<_> = stations
+                .iter()
+                .map(|(city, m)| (city.as_str(), m.count))
+                .collect();
+            assert_eq!(counts, [("Lima", 3), ("Oslo", 2)], "{name}");
+            assert!(stations[0].1.stddev_tenths().is_nan(), "{name}");
+
+            let tracked = file.measurements_with_stddev(&filter);
+            let (_, lima) = tracked.iter().find(|(city, _)| city == "Lima").unwrap();
+            assert_eq!(lima.avg_tenths(), stations[0].1.avg_tenths(), "{name}");
+            assert!((lima.stddev_tenths() - 129.6).abs() < 0.05, "{name}");
+
+            let thresholds = Thresholds {
+                max: Some(200),
+                ..Thresholds::default()
+            };
+            let limits = [("Lima".to_string(), thresholds.limits(&stations[0].1))];
+            let anomalies = file.anomalies(&limits);
+            let rows: Vec<_> = anomalies
+                .iter()
+                .map(|anomaly| (anomaly.line, anomaly.value, anomaly.row.as_str()))
+                .collect();
+            assert_eq!(rows, [(flagged_line, 400, flagged_row)], "{name}");
+
+            let diagnostics = file.diagnostics(&filter);
+            let (_, lima) = diagnostics.iter().find(|(city, _)| city == "Lima").unwrap();
+            assert_eq!(lima.count, 3, "{name}");
+            assert_eq!(lima.most_frequent(1), [(125, 2)], "{name}");
+
+            let tracked = file.tracked_measurements(&filter);
+            let (_, lima) = tracked.iter().find(|(city, _)| city == "Lima").unwrap();
+            let (first, last) = (lima.first.unwrap(), lima.last.unwrap());
+            let lines = file.line_numbers([first.offset, last.offset]);
+            assert_eq!(
+                (lines[&first.offset], lines[&last.offset]),
+                (first_line, last_line),
+                "{name}"
+            );
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_record_at_end_of_mapping() {
+        // a multiple of every page size, so nothing is mapped right after the last record
+        const LEN: usize = 1 << 16;
+        for last in ["Oslo;-1.2\n", "Oslo;-12.3"] {
+            let mut contents = String::new();
+            while contents.len() + 16 + last.len() <= LEN {
+                contents.push_str("Abc;1.0\n");
+            }
+            let filler = LEN - contents.len() - last.len() - ";1.0\n".len();
+            contents.push_str(&format!("{};1.0\n", "B".repeat(filler)));
+            contents.push_str(last);
+            assert_eq!(contents.len(), LEN);
+
+            let path = std::env::temp_dir().join("1brc-test-end-of-mapping.txt");
+            std::fs::write(&path, &contents).unwrap();
+            let stations = File::open(path.to_str().unwrap()).unwrap().parse();
+            std::fs::remove_file(path).unwrap();
+
+            let (city, oslo) = stations.last().unwrap();
+            assert_eq!(city, "Oslo");
+            let expected = last[5..].trim_end().parse::<f32>().unwrap();
+            assert_eq!((oslo.count, oslo.min, oslo.max), (1, expected, expected));
+        }
+    }
+
+    #[test]
+    fn test_parse_challenge_chunk() {
+        let measurements = parse_challenge_chunk(b"Lima;12.5\nOslo;-3.0\nLima;-0.5", true).unwrap();
+        let stats: Vec<_> = measurements
+            .iter()
+            .map(|(city, m)| (city.as_str(), m.min, m.max, m.count))
+            .collect();
+        assert_eq!(stats, [("Lima", -0.5, 12.5, 2), ("Oslo", -3.0, -3.0, 1)]);
+
+        for (input, error) in [
+            (&b"Lima;12.5\n\n"[..], "line 2: missing ';'"),
+            (b";1.0\n", "line 1: station names"),
+            (
+                b"Lima;1.0\nOslo;100.0\n",
+                "line 2: invalid temperature \"100.0\"",
+            ),
+            (b"Lima;1\n", "line 1: invalid temperature"),
+            (b"Lima;1.0\r\n", "line 1: invalid temperature"),
+            (b"\xff;1.0\n", "line 1: station names"),
+        ] {
+            let Err(message) = parse_challenge_chunk(input, true) else {
+                panic!("{input:?} should be rejected");
+            };
+            let message = message.to_string();
+            assert!(message.starts_with(error), "{message}");
+        }
+
+        // Unvalidated garbage still parses, without reading out of bounds
+        parse_challenge_chunk(b";;\n-;-\xff\n;", false).unwrap();
+
+        // More stations than the table starts with, validated or not
+        let many: String = (0..10_000).map(|i| format!("{i};1.0\n")).collect();
+        for validate in [true, false] {
+            let measurements = parse_challenge_chunk(many.as_bytes(), validate).unwrap();
+            assert_eq!(measurements.len(), 10_000);
+        }
+    }
+
+    fn temperature(tenths: i16) -> String {
+        let sign = if tenths < 0 { "-" } else { "" };
+        format!("{sign}{}.{}\n", tenths.abs() / 10, tenths.abs() % 10)
+    }
+
+    /// Parses `tenths` written at `align` bytes into a buffer, followed by
+    /// `next_row`, both from a padded buffer and from one ending at the newline.
+    fn parse_temp_at(tenths: i16, align: usize, next_row: &[u8]) -> ((i16, usize), (i16, usize)) {
+        let text = temperature(tenths);
+        let mut buffer = vec![0u8; align];
+        buffer.extend_from_slice(text.as_bytes());
+        buffer.extend_from_slice(next_row);
+        buffer.resize(buffer.len() + 8, 0);
+
+        let padded = unsafe {
+            let (value, next) = File::parse_temp(buffer.as_ptr().add(align));
+            (value, next.offset_from(buffer.as_ptr()) as usize)
+        };
+        let exact = unsafe { File::parse_temp_in(&buffer[..align + text.len()], align) };
+        (padded, exact)
+    }
+
+    #[test]
+    fn test_parse_temp_every_value() {
+        for tenths in -999..=999 {
+            for align in 0..8 {
+                let end = align + temperature(tenths).len();
+                assert_eq!(
+                    parse_temp_at(tenths, align, b"Hamburg;12.0\n"),
+                    ((tenths, end), (tenths, end)),
+                    "{tenths} at {align}"
+                );
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_parse_temp_ignores_next_row(
+            tenths in -999i16..=999,
+            align in 0usize..8,
+            next_row in proptest::collection::vec(any::<u8>(), 0..8),
+        ) {
+            let end = align + temperature(tenths).len();
+            prop_assert_eq!(
+                parse_temp_at(tenths, align, &next_row),
+                ((tenths, end), (tenths, end))
+            );
+        }
+
+        #[test]
+        fn test_split_lines_keeps_rows_whole(
+            buffer in proptest::collection::vec(
+                prop_oneof![1 => Just(b'\n'), 15 => any::<u8>()],
+                0..4096,
+            ),
+            num_chunks in 1usize..64,
+        ) {
+            let chunks = split_lines(&buffer, num_chunks);
+
+            prop_assert_eq!(&chunks.concat(), &buffer);
+            prop_assert!(chunks.iter().all(|chunk| !chunk.is_empty()));
+            for pair in chunks.windows(2) {
+                prop_assert_eq!(pair[0].last(), Some(&b'\n'));
+            }
+        }
     }
 }
@@ -0,0 +1,261 @@
+use std::str::FromStr;
+
+use anyhow::{Context, anyhow};
+use regex::Regex;
+
+use crate::{measurement::FinalMeasurement, report::Statistic};
+
+/// Include/exclude patterns on station names.
+///
+/// Patterns are globs (`*`, `?`, `[...]`) matched against the whole name,
+/// or regexes when prefixed with `re:`. A station is kept when it matches
+/// any include (or there are none) and no exclude.
+#[derive(Default)]
+pub struct NameFilter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl NameFilter {
+    pub fn include(&mut self, pattern: &str) -> anyhow::Result<()> {
+        self.include.push(compile(pattern)?);
+        Ok(())
+    }
+
+    pub fn exclude(&mut self, pattern: &str) -> anyhow::Result<()> {
+        self.exclude.push(compile(pattern)?);
+        Ok(())
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    #[inline(always)]
+    pub fn matches(&self, name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|re| re.is_match(name)))
+            && !self.exclude.iter().any(|re| re.is_match(name))
+    }
+}
+
+fn compile(pattern: &str) -> anyhow::Result<Regex> {
+    let regex = match pattern.strip_prefix("re:") {
+        Some(regex) => regex.to_string(),
+        None => glob_to_regex(pattern),
+    };
+    Regex::new(&regex).context(format!("invalid pattern: {pattern}"))
+}
+
+/// Translate a glob into an anchored regex; `[...]` classes are passed through
+/// with `!` negation mapped to `^`.
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::with_capacity(glob.len() + 8);
+    regex.push('^');
+
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '[' => {
+                regex.push('[');
+                if chars.next_if_eq(&'!').is_some() {
+                    regex.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        regex.push('\\');
+                    }
+                    regex.push(c);
+                }
+                regex.push(']');
+            }
+            _ => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+
+    regex.push('$');
+    regex
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Comparison {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+/// A condition on the final aggregates, such as `max > 40.0` or `count >= 1000`.
+#[derive(Clone, Copy, Debug)]
+pub struct Predicate {
+    statistic: Statistic,
+    comparison: Comparison,
+    value: f64,
+}
+
+impl Predicate {
+    #[inline(always)]
+    pub fn statistic(&self) -> Statistic {
+        self.statistic
+    }
+
+    #[inline(always)]
+    pub fn matches(&self, measurement: &FinalMeasurement) -> bool {
+        // compare at the f32 precision the statistics were computed in, so `avg == 18.3`
+        // holds; counts stay exact
+        let (actual, value) = match self.statistic {
+            Statistic::Count => (self.statistic.value(measurement), self.value),
+            _ => (
+                self.statistic.value(measurement) as f32 as f64,
+                self.value as f32 as f64,
+            ),
+        };
+
+        match self.comparison {
+            Comparison::Lt => actual < value,
+            Comparison::Le => actual <= value,
+            Comparison::Gt => actual > value,
+            Comparison::Ge => actual >= value,
+            Comparison::Eq => actual == value,
+            Comparison::Ne => actual != value,
+        }
+    }
+}
+
+impl FromStr for Predicate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        // earliest operator wins; at the same position the longer one, so `>=` is not read as `>`
+        let operators = [
+            (">=", Comparison::Ge),
+            ("<=", Comparison::Le),
+            ("==", Comparison::Eq),
+            ("!=", Comparison::Ne),
+            (">", Comparison::Gt),
+            ("<", Comparison::Lt),
+            ("=", Comparison::Eq),
+        ];
+
+        let (at, operator, comparison) = operators
+            .iter()
+            .filter_map(|&(op, cmp)| s.find(op).map(|at| (at, op, cmp)))
+            .min_by_key(|&(at, op, _)| (at, usize::MAX - op.len()))
+            .ok_or_else(|| anyhow!("predicate needs a comparison operator: {s}"))?;
+
+        let statistic = s[..at].trim().parse()?;
+        let value = s[at + operator.len()..]
+            .trim()
+            .parse::<f64>()
+            .context(format!("invalid number in predicate: {s}"))?;
+
+        Ok(Self {
+            statistic,
+            comparison,
+            value,
+        })
+    }
+}
+
+/// Keep only the stations that satisfy every predicate.
+pub fn retain(measurements: &mut Vec<(String, FinalMeasurement)>, predicates: &[Predicate]) {
+    if predicates.is_empty() {
+        return;
+    }
+    measurements.retain(|(_, measurement)| predicates.iter().all(|p| p.matches(measurement)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NameFilter, Predicate, retain};
+    use crate::measurement::{FinalMeasurement, Measurement};
+
+    fn matches(predicate: &str, measurement: &FinalMeasurement) -> bool {
+        predicate.parse::<Predicate>().unwrap().matches(measurement)
+    }
+
+    #[test]
+    fn test_name_filter_patterns() {
+        let mut filter = NameFilter::default();
+        filter.include("A*").unwrap();
+        filter.include("re:^Z.rich$").unwrap();
+        filter.exclude("A[!b]*").unwrap();
+
+        assert!(filter.matches("Abéché"));
+        assert!(filter.matches("Zürich"));
+        assert!(!filter.matches("Accra"));
+        assert!(!filter.matches("Oslo"));
+    }
+
+    #[test]
+    fn test_predicate_operators() {
+        // min 12.1, mean 18.3, max 24.6, count 3
+        let mut measurement = Measurement::new(121);
+        measurement.add(182);
+        measurement.add(246);
+        let measurement: FinalMeasurement = measurement.into();
+
+        for (predicate, expected) in [
+            ("mean > 18", true),
+            ("mean<18.3", false),
+            ("mean <= 18.3", true),
+            ("mean>=18.3", true),
+            ("mean == 18.3", true),
+            ("avg = 18.3", true),
+            ("mean != 18.3", false),
+            ("MIN<-5", false),
+            ("max>=-5", true),
+            ("range > 12.4", true),
+            ("count == 3", true),
+            ("count >= 4", false),
+        ] {
+            assert_eq!(matches(predicate, &measurement), expected, "{predicate}");
+        }
+    }
+
+    #[test]
+    fn test_predicate_compares_at_f32_precision() {
+        // the mean is 0.3 as an f32, and `0.3` has no exact f64 either; both round the same way
+        let mut measurement = Measurement::new(1);
+        measurement.add(5);
+        let measurement: FinalMeasurement = measurement.into();
+
+        assert!(matches("mean == 0.3", &measurement));
+        assert!(!matches("mean > 0.3", &measurement));
+        assert!(!matches("mean < 0.3", &measurement));
+    }
+
+    #[test]
+    fn test_invalid_predicates() {
+        for predicate in [
+            "mean",
+            "mean > ",
+            "mean > warm",
+            "median > 1",
+            "> 1",
+            "mean >> 1",
+        ] {
+            assert!(predicate.parse::<Predicate>().is_err(), "{predicate}");
+        }
+    }
+
+    #[test]
+    fn test_retain_needs_every_predicate() {
+        let mut measurements: Vec<(String, FinalMeasurement)> = [("Abha", 180), ("Oslo", 30)]
+            .into_iter()
+            .map(|(city, value)| (city.to_string(), Measurement::new(value).into()))
+            .collect();
+        let predicates = ["mean > 1".parse().unwrap(), "max < 10".parse().unwrap()];
+
+        retain(&mut measurements, &predicates);
+        let names: Vec<_> = measurements.iter().map(|(city, _)| city.as_str()).collect();
+        assert_eq!(names, ["Oslo"]);
+    }
+}
@@ -0,0 +1,423 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Write,
+    str::FromStr,
+};
+
+use anyhow::{Context, anyhow, bail};
+
+use crate::{
+    measurement::{FinalMeasurement, Measurement},
+    output::Writer,
+    sort::{self, SortOrder},
+};
+
+/// How a [`Rollup`] is laid out when written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Layout {
+    /// One indented tree, from the global aggregate down to the stations, as
+    /// plain text, so only for the text formats.
+    Nested,
+    /// One section per level, written with [`Writer::write_sections`].
+    #[default]
+    Sections,
+}
+
+impl FromStr for Layout {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "nested" | "tree" => Ok(Layout::Nested),
+            "sections" => Ok(Layout::Sections),
+            _ => Err(anyhow!("unknown group layout: {s}")),
+        }
+    }
+}
+
+/// Station-to-group mapping, loaded from `station;region[;country...]` lines,
+/// finest group first. An optional first line starting with `#` names the
+/// columns, e.g. `#station;region;country`.
+pub struct Hierarchy {
+    levels: Vec<String>,
+    groups: HashMap<String, Vec<String>>,
+}
+
+impl Hierarchy {
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path).context(format!("Failed to read {path}"))?;
+
+        let mut levels = None;
+        let mut groups = HashMap::new();
+        for (i, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Some(header) = line.strip_prefix('#') {
+                if levels.is_none() && groups.is_empty() {
+                    levels = Some(header.split(';').skip(1).map(str::to_string).collect());
+                }
+                continue;
+            }
+
+            let mut columns = line.split(';');
+            let station = columns.next().unwrap_or_default().to_string();
+            let parents: Vec<String> = columns.map(str::to_string).collect();
+            if parents.is_empty() {
+                bail!("{path}:{}: expected `station;group[;...]`", i + 1);
+            }
+
+            let levels: &mut Vec<String> = levels
+                .get_or_insert_with(|| (1..=parents.len()).map(|l| format!("level {l}")).collect());
+            if parents.len() != levels.len() {
+                bail!(
+                    "{path}:{}: expected {} group columns, found {}",
+                    i + 1,
+                    levels.len(),
+                    parents.len()
+                );
+            }
+
+            groups.insert(station, parents);
+        }
+
+        Ok(Self {
+            levels: levels.unwrap_or_default(),
+            groups,
+        })
+    }
+
+    /// Merge the per-station aggregates into every group above them and into a
+    /// global total. Unmapped stations still count towards the global total.
+    pub fn roll_up(&self, measurements: &[(String, Measurement)]) -> Rollup {
+        let mut root = Node::default();
+        let mut unmapped = Vec::new();
+
+        for (city, measurement) in measurements {
+            root.measurement.merge(measurement);
+
+            let Some(parents) = self.groups.get(city) else {
+                unmapped.push(city.clone());
+                continue;
+            };
+
+            // walk from the coarsest group down to the station itself
+            let mut node = &mut root;
+            for group in parents.iter().rev().chain(std::iter::once(city)) {
+                node = node.children.entry(group.clone()).or_default();
+                node.measurement.merge(measurement);
+            }
+        }
+
+        unmapped.sort_unstable();
+        Rollup {
+            levels: self.levels.clone(),
+            root,
+            unmapped,
+        }
+    }
+}
+
+pub struct Node {
+    pub measurement: Measurement,
+    pub children: BTreeMap<String, Node>,
+}
+
+impl Default for Node {
+    #[inline(always)]
+    fn default() -> Self {
+        Self {
+            measurement: Measurement::empty(),
+            children: BTreeMap::new(),
+        }
+    }
+}
+
+pub struct Rollup {
+    /// Group level names, finest first, as in the mapping file.
+    pub levels: Vec<String>,
+    /// The global aggregate; its descendants are the groups, coarsest first, then the stations.
+    pub root: Node,
+    /// Stations that were not in the mapping, in byte order.
+    pub unmapped: Vec<String>,
+}
+
+impl Rollup {
+    /// Every level from the coarsest group down to the stations, with groups of
+    /// the same name at the same level merged.
+    pub fn sections(&self) -> Vec<(String, Vec<(String, FinalMeasurement)>)> {
+        let names = self
+            .levels
+            .iter()
+            .rev()
+            .cloned()
+            .chain(std::iter::once("station".to_string()));
+
+        let mut level = vec![&self.root];
+        let mut sections = Vec::with_capacity(self.levels.len() + 1);
+        for name in names {
+            let mut merged: BTreeMap<&str, Measurement> = BTreeMap::new();
+            for (group, node) in level.iter().flat_map(|node| node.children.iter()) {
+                merged
+                    .entry(group)
+                    .or_insert_with(Measurement::empty)
+                    .merge(&node.measurement);
+            }
+            level = level
+                .iter()
+                .flat_map(|node| node.children.values())
+                .collect();
+
+            let rows = merged
+                .into_iter()
+                .map(|(group, measurement)| (group.to_string(), measurement.into()))
+                .collect();
+            sections.push((name, rows));
+        }
+        sections
+    }
+
+    /// Write every level, the groups of each level and the children of each
+    /// group in `order`, see [`sort::sort`].
+    pub fn write(
+        &self,
+        output: &mut impl Write,
+        layout: Layout,
+        writer: &Writer,
+        order: SortOrder,
+        reverse: bool,
+    ) -> std::io::Result<()> {
+        match layout {
+            Layout::Nested => self.write_nested(output, "global", &self.root, 0, order, reverse)?,
+            Layout::Sections => {
+                // empty input, or a --where that dropped every station, has no total
+                let global: Vec<_> = (self.root.measurement.count > 0)
+                    .then(|| ("global".to_string(), self.root.measurement.into()))
+                    .into_iter()
+                    .collect();
+                let mut sections = self.sections();
+                for (_, rows) in &mut sections {
+                    sort::sort(rows, order, reverse);
+                }
+                sections.insert(0, ("global".to_string(), global));
+                writer.write_sections(output, "level", &sections)?;
+            }
+        }
+
+        // the other formats are a single document; the caller reports the count
+        if !self.unmapped.is_empty() && writer.format().is_text() {
+            writeln!(output, "\n## unmapped")?;
+            for city in &self.unmapped {
+                writeln!(output, "{city}")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_nested(
+        &self,
+        output: &mut impl Write,
+        name: &str,
+        node: &Node,
+        depth: usize,
+        order: SortOrder,
+        reverse: bool,
+    ) -> std::io::Result<()> {
+        // only the root can be empty, and then it has no children either
+        if node.measurement.count == 0 {
+            return Ok(());
+        }
+
+        let measurement: FinalMeasurement = node.measurement.into();
+        writeln!(
+            output,
+            "{:indent$}{name}={:.1}/{:.1}/{:.1} ({})",
+            "",
+            measurement.min,
+            measurement.avg,
+            measurement.max,
+            measurement.count,
+            indent = depth * 2
+        )?;
+
+        let mut children: Vec<(String, FinalMeasurement)> = node
+            .children
+            .iter()
+            .map(|(child, node)| (child.clone(), node.measurement.into()))
+            .collect();
+        sort::sort(&mut children, order, reverse);
+        for (child, _) in &children {
+            let node = &node.children[child];
+            self.write_nested(output, child, node, depth + 1, order, reverse)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Hierarchy, Layout};
+    use crate::{
+        measurement::Measurement,
+        output::{Format, Writer},
+        report::Statistic,
+        sort::SortOrder,
+    };
+
+    fn load(name: &str, contents: &str) -> Hierarchy {
+        let path = std::env::temp_dir().join(format!("1brc-test-{name}.csv"));
+        std::fs::write(&path, contents).unwrap();
+        let hierarchy = Hierarchy::load(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(path).unwrap();
+        hierarchy
+    }
+
+    #[test]
+    fn test_roll_up_merges_into_every_level() {
+        let hierarchy = load(
+            "hierarchy",
+            "#station;country;continent\nBerlin;Germany;Europe\nParis;France;Europe\n",
+        );
+
+        let mut berlin = Measurement::new(100);
+        berlin.add(80);
+        let stations = vec![
+            ("Berlin".to_string(), berlin),
+            ("Paris".to_string(), Measurement::new(150)),
+            ("Tokyo".to_string(), Measurement::new(200)),
+        ];
+        let rollup = hierarchy.roll_up(&stations);
+
+        assert_eq!(rollup.unmapped, ["Tokyo"]);
+        assert_eq!(rollup.root.measurement.count, 4);
+
+        let sections = rollup.sections();
+        let names: Vec<_> = sections.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["continent", "country", "station"]);
+
+        let (_, continents) = &sections[0];
+        assert_eq!(continents.len(), 1);
+        assert_eq!(continents[0].0, "Europe");
+        assert_eq!(
+            (
+                continents[0].1.min,
+                continents[0].1.max,
+                continents[0].1.count
+            ),
+            (8.0, 15.0, 3)
+        );
+    }
+
+    #[test]
+    fn test_empty_roll_up_has_no_total() {
+        // empty input, or a --where that drops every station
+        let hierarchy = load("hierarchy-empty", "#station;country\nBerlin;Germany\n");
+        let rollup = hierarchy.roll_up(&[]);
+        let writer = Writer::new(Format::Challenge, false);
+
+        let mut nested = Vec::new();
+        rollup
+            .write(
+                &mut nested,
+                Layout::Nested,
+                &writer,
+                SortOrder::Bytes,
+                false,
+            )
+            .unwrap();
+        assert_eq!(String::from_utf8(nested).unwrap(), "");
+
+        let mut sections = Vec::new();
+        rollup
+            .write(
+                &mut sections,
+                Layout::Sections,
+                &writer,
+                SortOrder::Bytes,
+                false,
+            )
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(sections).unwrap(),
+            "## global\n{}\n\n## country\n{}\n\n## station\n{}\n"
+        );
+    }
+
+    #[test]
+    fn test_groups_follow_the_sort_order() {
+        let hierarchy = load(
+            "hierarchy-sort",
+            "#station;country\nBerlin;Germany\nHamburg;Germany\nLyon;France\n",
+        );
+        let stations = vec![
+            ("Berlin".to_string(), Measurement::new(100)),
+            ("Hamburg".to_string(), Measurement::new(300)),
+            ("Lyon".to_string(), Measurement::new(200)),
+        ];
+        let rollup = hierarchy.roll_up(&stations);
+        let writer = Writer::new(Format::Challenge, false);
+        let order = SortOrder::Statistic(Statistic::Max);
+
+        let mut nested = Vec::new();
+        rollup
+            .write(&mut nested, Layout::Nested, &writer, order, true)
+            .unwrap();
+        let lines: Vec<_> = String::from_utf8(nested)
+            .unwrap()
+            .lines()
+            .map(|line| line.split('=').next().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "global",
+                "  Germany",
+                "    Hamburg",
+                "    Berlin",
+                "  France",
+                "    Lyon"
+            ]
+        );
+
+        let mut sections = Vec::new();
+        rollup
+            .write(&mut sections, Layout::Sections, &writer, order, true)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(sections).unwrap(),
+            "## global\n{global=10.0/20.0/30.0}\n\n\
+             ## country\n{Germany=10.0/20.0/30.0, France=20.0/20.0/20.0}\n\n\
+             ## station\n{Hamburg=30.0/30.0/30.0, Lyon=20.0/20.0/20.0, Berlin=10.0/10.0/10.0}\n"
+        );
+    }
+
+    #[test]
+    fn test_sections_are_one_document() {
+        let hierarchy = load("hierarchy-csv", "#station;country\nBerlin;Germany\n");
+        let stations = vec![
+            ("Berlin".to_string(), Measurement::new(100)),
+            ("Tokyo".to_string(), Measurement::new(200)),
+        ];
+        let rollup = hierarchy.roll_up(&stations);
+        let writer = Writer::new(Format::Csv, false).with_label("group");
+
+        let mut actual = Vec::new();
+        rollup
+            .write(
+                &mut actual,
+                Layout::Sections,
+                &writer,
+                SortOrder::Bytes,
+                false,
+            )
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(actual).unwrap(),
+            "level,group,min,mean,max,count\r\n\
+             global,global,10.0,15.0,20.0,2\r\n\
+             country,Germany,10.0,10.0,10.0,1\r\n\
+             station,Berlin,10.0,10.0,10.0,1\r\n"
+        );
+    }
+}
@@ -1,34 +1,44 @@
-use super::measurement::Measurement;
+use super::measurement::{Accumulate, Aggregate, Measurement};
 
+/// Slots a new table starts with. It doubles whenever it gets more than
+/// half full, so a probe always ends on an empty slot.
 const CAPACITY: usize = 4096;
-const MASK: usize = CAPACITY - 1;
 
-pub struct HashMap<'a> {
-    entries: Box<[Entry<'a>]>,
+pub struct HashMap<'a, V = Measurement> {
+    entries: Box<[Entry<'a, V>]>,
+    mask: usize,
     pub len: usize,
 }
 
-struct Entry<'a> {
+struct Entry<'a, V> {
     hash: u64,
     key: &'a [u8],
-    measurement: Measurement,
+    /// The first [`PREFIX`] bytes of `key`, so most keys are compared
+    /// without leaving the slot for the input they point into.
+    prefix: [u8; PREFIX],
+    measurement: V,
 }
 
-impl<'a> Default for Entry<'a> {
+/// Key bytes kept inline in every slot. With a [`Tally`](super::measurement::Tally)
+/// that makes a slot exactly one cache line.
+const PREFIX: usize = 16;
+
+impl<'a, V: Default> Default for Entry<'a, V> {
     #[inline(always)]
     fn default() -> Self {
         Self {
             hash: 0,
             key: &[],
-            measurement: Measurement::empty(),
+            prefix: [0; PREFIX],
+            measurement: V::default(),
         }
     }
 }
 
 /// Full-content hash using CRC32C intrinsics (1 cycle/8 bytes throughput).
-/// Hashes ALL bytes for collision safety, enabling hash-only key matching.
+/// Hashes ALL bytes, so a matching hash is almost always the same key.
 #[inline(always)]
-#[cfg(target_arch = "x86_64")]
+#[cfg(all(target_arch = "x86_64", not(miri)))]
 fn hash_key(key: &[u8]) -> u64 {
     use std::arch::x86_64::_mm_crc32_u64;
 
@@ -65,7 +75,7 @@ fn hash_key(key: &[u8]) -> u64 {
 /// Full-content hash using ARM CRC32C intrinsics.
 /// Same algorithm as x86_64 variant, using __crc32cd/w for equivalent throughput.
 #[inline(always)]
-#[cfg(target_arch = "aarch64")]
+#[cfg(all(target_arch = "aarch64", not(miri)))]
 fn hash_key(key: &[u8]) -> u64 {
     use std::arch::aarch64::{__crc32cd, __crc32cw};
 
@@ -99,21 +109,145 @@ fn hash_key(key: &[u8]) -> u64 {
     }
 }
 
-impl<'a> HashMap<'a> {
+/// Software CRC32C of the 8 little-endian bytes of `data`, matching `_mm_crc32_u64`.
+#[cfg(any(miri, all(test, target_arch = "x86_64")))]
+fn crc32c_u64(crc: u64, data: u64) -> u64 {
+    let mut crc = crc as u32;
+    for byte in data.to_le_bytes() {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0x82F6_3B78 & (crc & 1).wrapping_neg());
+        }
+    }
+    crc as u64
+}
+
+/// The x86_64 [`hash_key`] without intrinsics, for Miri.
+#[cfg(any(miri, all(test, target_arch = "x86_64")))]
+fn hash_key_scalar(key: &[u8]) -> u64 {
+    let len = key.len();
+    let word = |i: usize| u64::from_le_bytes(key[i..i + 8].try_into().unwrap());
+
+    let crc = if len >= 16 {
+        let mut h = crc32c_u64(len as u64, word(0));
+        let mut i = 8usize;
+        while i + 8 <= len {
+            h = crc32c_u64(h, word(i));
+            i += 8;
+        }
+        crc32c_u64(h, word(len - 8))
+    } else if len >= 8 {
+        crc32c_u64(crc32c_u64(len as u64, word(0)), word(len - 8))
+    } else if len >= 4 {
+        let lo = u32::from_le_bytes(key[..4].try_into().unwrap()) as u64;
+        let hi = u32::from_le_bytes(key[len - 4..].try_into().unwrap()) as u64;
+        crc32c_u64(len as u64, lo | (hi << 32))
+    } else {
+        let mut buf = [0u8; 8];
+        buf[..len].copy_from_slice(key);
+        crc32c_u64(len as u64, u64::from_le_bytes(buf))
+    };
+
+    crc | 1
+}
+
+#[inline(always)]
+#[cfg(miri)]
+fn hash_key(key: &[u8]) -> u64 {
+    hash_key_scalar(key)
+}
+
+impl<'a, V> Entry<'a, V> {
+    /// Take an empty slot for `key`.
+    #[inline(always)]
+    fn claim(&mut self, key: &'a [u8], hash: u64) {
+        let len = key.len().min(PREFIX);
+        self.hash = hash;
+        self.key = key;
+        self.prefix[..len].copy_from_slice(&key[..len]);
+    }
+
+    /// Whether this slot holds `key`, whose hash is `hash`. The short keys
+    /// most stations have are compared as two overlapping words of the
+    /// inline prefix instead of through `memcmp` on the stored key.
+    #[inline(always)]
+    fn holds(&self, key: &[u8], hash: u64) -> bool {
+        let len = key.len();
+        if self.hash != hash || self.key.len() != len {
+            return false;
+        }
+
+        unsafe {
+            let (a, b) = (self.prefix.as_ptr(), key.as_ptr());
+            if (8..=PREFIX).contains(&len) {
+                let head = (a as *const u64).read_unaligned() ^ (b as *const u64).read_unaligned();
+                let tail = (a.add(len - 8) as *const u64).read_unaligned()
+                    ^ (b.add(len - 8) as *const u64).read_unaligned();
+                return head | tail == 0;
+            }
+            if (4..8).contains(&len) {
+                let head = (a as *const u32).read_unaligned() ^ (b as *const u32).read_unaligned();
+                let tail = (a.add(len - 4) as *const u32).read_unaligned()
+                    ^ (b.add(len - 4) as *const u32).read_unaligned();
+                return head | tail == 0;
+            }
+        }
+        if len < 4 {
+            return self.prefix[..len] == *key;
+        }
+        self.key == key
+    }
+}
+
+impl<'a, V: Default> HashMap<'a, V> {
     #[inline(always)]
     pub fn new() -> Self {
-        let entries = (0..CAPACITY)
+        Self::with_capacity(CAPACITY)
+    }
+
+    fn with_capacity(capacity: usize) -> Self {
+        let entries = (0..capacity)
             .map(|_| Entry::default())
             .collect::<Vec<_>>()
             .into_boxed_slice();
-        Self { entries, len: 0 }
+        Self {
+            entries,
+            mask: capacity - 1,
+            len: 0,
+        }
+    }
+
+    /// Whether taking one more empty slot would leave the table more than half full.
+    #[inline(always)]
+    fn is_full(&self) -> bool {
+        (self.len + 1) * 2 > self.entries.len()
+    }
+
+    /// Doubles the table, moving every entry to its slot under the new mask.
+    #[cold]
+    #[inline(never)]
+    fn grow(&mut self) {
+        let old = std::mem::replace(self, Self::with_capacity(self.entries.len() * 2));
+        self.len = old.len;
+        for entry in old.entries.into_vec() {
+            if entry.hash == 0 {
+                continue;
+            }
+
+            let mut idx = (entry.hash as usize) & self.mask;
+            while self.entries[idx].hash != 0 {
+                idx = (idx + 1) & self.mask;
+            }
+            self.entries[idx] = entry;
+        }
     }
 
     /// Compute hash and prefetch the likely hash table slot.
     #[inline(always)]
     pub fn prefetch_slot(&self, key: &[u8]) -> u64 {
         let hash = hash_key(key);
-        let idx = (hash as usize) & MASK;
+        let idx = (hash as usize) & self.mask;
+        #[cfg(not(miri))]
         unsafe {
             let ptr = self.entries.as_ptr().add(idx) as *const u8;
             #[cfg(target_arch = "x86_64")]
@@ -124,62 +258,298 @@ impl<'a> HashMap<'a> {
         hash
     }
 
+    /// The value for `key`, inserting `V::default()` if it is not present yet.
     #[inline(always)]
-    pub fn insert_with_hash(&mut self, key: &'a [u8], value: i16, hash: u64) {
-        let mut idx = (hash as usize) & MASK;
+    pub fn entry_with_hash(&mut self, key: &'a [u8], hash: u64) -> &mut V {
+        let mut idx = (hash as usize) & self.mask;
 
         loop {
-            let entry = unsafe { self.entries.get_unchecked_mut(idx) };
+            let entry = unsafe { &mut *self.entries.as_mut_ptr().add(idx) };
 
             if entry.hash == 0 {
-                entry.hash = hash;
-                entry.key = key;
-                entry.measurement = Measurement::new(value);
+                if self.is_full() {
+                    self.grow();
+                    idx = (hash as usize) & self.mask;
+                    continue;
+                }
+                let entry = unsafe { &mut *self.entries.as_mut_ptr().add(idx) };
+                entry.claim(key, hash);
                 self.len += 1;
-                return;
+                return &mut entry.measurement;
             }
 
-            // CRC32C of all bytes + length: collision astronomically unlikely
-            if entry.hash == hash {
-                entry.measurement.add(value);
-                return;
+            // CRC32C is linear, so crafted keys can collide: compare the bytes too
+            if entry.holds(key, hash) {
+                return &mut entry.measurement;
             }
 
-            idx = (idx + 1) & MASK;
+            idx = (idx + 1) & self.mask;
         }
     }
 
-    pub fn merge(&mut self, other: HashMap<'a>) {
+    /// The value stored under `key`, whose hash is `hash`, if any.
+    #[inline(always)]
+    pub fn get_with_hash(&self, key: &[u8], hash: u64) -> Option<&V> {
+        let mut idx = (hash as usize) & self.mask;
+
+        loop {
+            let entry = unsafe { self.entries.get_unchecked(idx) };
+            if entry.holds(key, hash) {
+                return Some(&entry.measurement);
+            }
+            if entry.hash == 0 {
+                return None;
+            }
+            idx = (idx + 1) & self.mask;
+        }
+    }
+
+    pub fn into_iter(self) -> impl Iterator<Item = (&'a [u8], V)> {
+        self.entries
+            .into_vec()
+            .into_iter()
+            .filter(|e| e.hash != 0)
+            .map(|e| (e.key, e.measurement))
+    }
+}
+
+impl<'a, V: Aggregate> HashMap<'a, V> {
+    pub fn merge(&mut self, other: HashMap<'a, V>) {
         let mut remaining = other.len;
-        for entry in other.entries.iter() {
+        for entry in other.entries.into_vec() {
             if remaining == 0 { break; }
             if entry.hash == 0 { continue; }
             remaining -= 1;
 
-            let mut idx = (entry.hash as usize) & MASK;
+            let mut idx = (entry.hash as usize) & self.mask;
             loop {
                 let self_entry = unsafe { self.entries.get_unchecked_mut(idx) };
                 if self_entry.hash == 0 {
-                    self_entry.hash = entry.hash;
-                    self_entry.key = entry.key;
-                    self_entry.measurement = Measurement::new_from(&entry.measurement);
+                    if self.is_full() {
+                        self.grow();
+                        idx = (entry.hash as usize) & self.mask;
+                        continue;
+                    }
+                    *unsafe { self.entries.get_unchecked_mut(idx) } = entry;
                     self.len += 1;
                     break;
                 }
-                if self_entry.hash == entry.hash {
+                if self_entry.holds(entry.key, entry.hash) {
                     self_entry.measurement.merge(&entry.measurement);
                     break;
                 }
-                idx = (idx + 1) & MASK;
+                idx = (idx + 1) & self.mask;
             }
         }
     }
+}
 
-    pub fn into_iter(self) -> impl Iterator<Item = (&'a [u8], Measurement)> {
-        self.entries
-            .into_vec()
+impl<'a, V: Accumulate> HashMap<'a, V> {
+    #[inline(always)]
+    pub fn insert_with_hash(&mut self, key: &'a [u8], value: i32, hash: u64) {
+        let mut idx = (hash as usize) & self.mask;
+
+        loop {
+            let entry = unsafe { self.entries.get_unchecked_mut(idx) };
+
+            // CRC32C is linear, so crafted keys can collide: compare the bytes too
+            if entry.holds(key, hash) {
+                entry.measurement.add(value);
+                return;
+            }
+
+            if entry.hash == 0 {
+                self.insert_new(idx, key, value, hash);
+                return;
+            }
+
+            idx = (idx + 1) & self.mask;
+        }
+    }
+
+    /// First row of `key`, whose probe ended on the empty slot `idx`. Out of
+    /// line, so growing and copying the key never take registers from the
+    /// row loop.
+    #[cold]
+    #[inline(never)]
+    fn insert_new(&mut self, mut idx: usize, key: &'a [u8], value: i32, hash: u64) {
+        if self.is_full() {
+            self.grow();
+            idx = (hash as usize) & self.mask;
+            while self.entries[idx].hash != 0 {
+                idx = (idx + 1) & self.mask;
+            }
+        }
+
+        let entry = &mut self.entries[idx];
+        entry.claim(key, hash);
+        entry.measurement = V::new(value);
+        self.len += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    #[cfg(target_arch = "x86_64")]
+    use super::hash_key_scalar;
+    use super::{HashMap, Measurement, hash_key};
+
+    const STATIONS: [&[u8]; 6] = [
+        b"Oslo",
+        b"Lima",
+        b"Hamburg",
+        b"Ouagadougou",
+        b"Al",
+        b"Llanfairpwllgwyngyll",
+    ];
+
+    /// Min, max, count, mean and standard deviation of a station, in tenths.
+    type Stats = (i32, i32, usize, f64, f64);
+
+    fn map_of(rows: &[(usize, i32)]) -> HashMap<'static> {
+        let mut map = HashMap::new();
+        for &(station, value) in rows {
+            let key = STATIONS[station];
+            map.insert_with_hash(key, value, hash_key(key));
+        }
+        map
+    }
+
+    fn contents(map: HashMap<'static>) -> Vec<(&'static [u8], Stats)> {
+        let mut contents: Vec<_> = map
             .into_iter()
-            .filter(|e| e.hash != 0)
-            .map(|e| (e.key, e.measurement))
+            .map(|(key, m)| {
+                let stats = (
+                    m.min_tenths(),
+                    m.max_tenths(),
+                    m.count,
+                    m.mean_tenths(),
+                    m.stddev_tenths(),
+                );
+                (key, stats)
+            })
+            .collect();
+        contents.sort_unstable_by(|a, b| a.0.cmp(b.0));
+        contents
+    }
+
+    fn rows() -> impl Strategy<Value = Vec<(usize, i32)>> {
+        proptest::collection::vec((0..STATIONS.len(), -999..=999), 0..64)
+    }
+
+    #[test]
+    fn test_colliding_keys_stay_apart() {
+        // Found by fuzzing: CRC32C is linear, so these two hash the same on x86_64
+        let (a, b): (&[u8], &[u8]) = (b".", b"-\0");
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(hash_key(a), hash_key(b));
+
+        let mut map: HashMap = HashMap::new();
+        map.insert_with_hash(a, 10, hash_key(a));
+        map.insert_with_hash(b, 20, hash_key(b));
+        assert_eq!(
+            map.get_with_hash(a, hash_key(a)).map(|m| m.max_tenths()),
+            Some(10)
+        );
+        assert_eq!(
+            map.get_with_hash(b, hash_key(b)).map(|m| m.max_tenths()),
+            Some(20)
+        );
+
+        let mut merged = HashMap::new();
+        merged.insert_with_hash(a, 30, hash_key(a));
+        merged.merge(map);
+        assert_eq!(contents(merged).len(), 2);
+    }
+
+    #[test]
+    fn test_grows_past_capacity() {
+        // the challenge allows 10,000 stations, more than the table starts with
+        let keys: Vec<_> = (0..10_000)
+            .map(|i| format!("station {i}").into_bytes())
+            .collect();
+        let (mut first, mut second) = (HashMap::new(), HashMap::new());
+        for (i, key) in keys.iter().enumerate() {
+            first.insert_with_hash(key, i as i32, hash_key(key));
+            *second.entry_with_hash(key, hash_key(key)) = Measurement::new(-(i as i32));
+        }
+        assert_eq!((first.len, second.len), (10_000, 10_000));
+
+        first.merge(second);
+        assert_eq!(first.len, 10_000);
+        for (i, key) in keys.iter().enumerate() {
+            let measurement = first.get_with_hash(key, hash_key(key)).unwrap();
+            let i = i as i32;
+            assert_eq!(
+                (measurement.min_tenths(), measurement.max_tenths()),
+                (-i, i)
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_hash_key_ignores_surrounding_bytes(
+            key in proptest::collection::vec(any::<u8>(), 0..48),
+            before in proptest::collection::vec(any::<u8>(), 0..8),
+            after in proptest::collection::vec(any::<u8>(), 0..8),
+        ) {
+            let buffer = [&before[..], &key, &after].concat();
+            let embedded = &buffer[before.len()..before.len() + key.len()];
+            let hash = hash_key(&key);
+
+            prop_assert_eq!(hash_key(embedded), hash);
+            // 0 marks an empty slot
+            prop_assert_eq!(hash & 1, 1);
+        }
+
+        #[test]
+        fn test_hash_key_covers_every_byte(
+            key in proptest::collection::vec(any::<u8>(), 1..48),
+            index in any::<prop::sample::Index>(),
+            flip in 1u8..,
+        ) {
+            let mut changed = key.clone();
+            changed[index.index(key.len())] ^= flip;
+            prop_assert_ne!(hash_key(&changed), hash_key(&key));
+
+            let mut longer = key.clone();
+            longer.push(0);
+            prop_assert_ne!(hash_key(&longer), hash_key(&key));
+        }
+
+        #[test]
+        #[cfg(target_arch = "x86_64")]
+        fn test_hash_key_scalar_matches_crc32c(key in proptest::collection::vec(any::<u8>(), 0..48)) {
+            prop_assert_eq!(hash_key_scalar(&key), hash_key(&key));
+        }
+
+        #[test]
+        fn test_merge_is_commutative(a in rows(), b in rows()) {
+            let mut ab = map_of(&a);
+            ab.merge(map_of(&b));
+            let mut ba = map_of(&b);
+            ba.merge(map_of(&a));
+
+            let merged = contents(ab);
+            prop_assert_eq!(&merged, &contents(ba));
+            prop_assert_eq!(&merged, &contents(map_of(&[a.as_slice(), &b].concat())));
+        }
+
+        #[test]
+        fn test_merge_is_associative(a in rows(), b in rows(), c in rows()) {
+            let mut left = map_of(&a);
+            left.merge(map_of(&b));
+            left.merge(map_of(&c));
+
+            let mut right = map_of(&b);
+            right.merge(map_of(&c));
+            let mut grouped = map_of(&a);
+            grouped.merge(right);
+
+            prop_assert_eq!(contents(left), contents(grouped));
+        }
     }
 }
@@ -1,9 +1,22 @@
 use anyhow::Context;
-use std::io::Write;
 
-mod file;
+pub mod alias;
+pub mod anomaly;
+pub mod audit;
+pub mod diagnostics;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod file;
+pub mod filter;
+pub mod group;
 mod hashmap;
-mod measurement;
+pub mod measurement;
+pub mod output;
+pub mod reference;
+pub mod report;
+pub mod schema;
+pub mod sort;
+pub mod window;
 
 pub static NUM_WORKERS: usize = 16;
 pub static IN_FILE_PATH: &str = "./measurements.txt";
@@ -27,20 +40,9 @@ pub fn perform_full_challenge() -> anyhow::Result<()> {
     let mut output = std::fs::File::create(OUT_FILE_PATH)
         .context(format!("Failed to create {OUT_FILE_PATH}"))?;
 
-    write!(output, "{{").context(format!("Failed to write to {OUT_FILE_PATH}"))?;
-    for (i, (city, measurement)) in measurements.iter().enumerate() {
-        write!(
-            output,
-            "{}={:.1}/{:.1}/{:.1}",
-            city, measurement.min, measurement.avg, measurement.max
-        )
+    output::Writer::new(output::Format::Challenge, false)
+        .write(&mut output, &measurements)
         .context(format!("Failed to write to {OUT_FILE_PATH}"))?;
 
-        if i != measurements.len() - 1 {
-            write!(output, ", ").context(format!("Failed to write to {OUT_FILE_PATH}"))?;
-        }
-    }
-    write!(output, "}}\n").context(format!("Failed to write to {OUT_FILE_PATH}"))?;
-
     Ok(())
 }
@@ -1,44 +1,337 @@
-use anyhow::Context;
-use one_billion_row_challenge::{IN_FILE_PATH, NUM_WORKERS, OUT_FILE_PATH};
-use std::io::Write;
+use anyhow::{Context, bail};
+use one_billion_row_challenge::{
+    NUM_WORKERS,
+    anomaly::{self, Limits, Thresholds},
+    audit, diagnostics, file, filter,
+    measurement::{FinalMeasurement, Measurement, finalize},
+    output::Writer,
+    sort,
+    sort::SortOrder,
+    window::{self, Window},
+};
+use std::{collections::HashMap, io::Write};
 
-mod file;
-mod hashmap;
-mod measurement;
+mod args;
 
 fn main() -> anyhow::Result<()> {
-    if std::fs::metadata("{OUT_FILE_PATH}").is_ok() {
-        std::fs::remove_file("{OUT_FILE_PATH}").context("Failed to remove {OUT_FILE_PATH}")?;
-    }
+    let args = args::Args::parse()?;
 
     println!("Number of workers: {}", NUM_WORKERS);
 
     let start = std::time::Instant::now();
 
-    let file = file::File::open(IN_FILE_PATH).context(format!("Failed to open {IN_FILE_PATH}"))?;
+    let in_path = &args.input;
+    let multi = args.schema.value_columns().len() > 1;
+    let file = file::File::open_with(in_path, args.schema.clone())
+        .context(format!("Failed to open {in_path}"))?;
+
+    if args.diagnostics {
+        return write_diagnostics(&args, &file, start);
+    }
+    if args.first_last {
+        return write_first_last(&args, &file, start);
+    }
+    if let Some(window) = args.window {
+        return write_windows(&args, &file, window, start);
+    }
+    if multi {
+        return write_metrics(&args, &file, start);
+    }
+
+    let stations = if args.needs_stddev() {
+        file.measurements_with_stddev(&args.names)
+    } else {
+        file.measurements(&args.names)
+    };
+    let raw_names: Vec<String> = match args.anomalies {
+        Some(_) => stations.iter().map(|(city, _)| city.clone()).collect(),
+        None => Vec::new(),
+    };
+    let stations = args.normalizer.apply(stations);
+
+    if let Some((path, thresholds)) = &args.anomalies {
+        write_anomalies(&args, &file, &raw_names, &stations, path, thresholds)?;
+    }
+
+    if let Some((mapping, hierarchy)) = &args.group_by {
+        let mut stations = stations;
+        stations.retain(|(_, measurement)| {
+            let measurement = FinalMeasurement::new(*measurement);
+            args.predicates.iter().all(|p| p.matches(&measurement))
+        });
+        let rollup = hierarchy.roll_up(&stations);
+
+        println!("Calculations took {:?}", start.elapsed());
+        if !rollup.unmapped.is_empty() {
+            println!("{} stations missing from {mapping}", rollup.unmapped.len());
+        }
+
+        let out_path = &args.output;
+        let output =
+            std::fs::File::create(out_path).context(format!("Failed to create {out_path}"))?;
+        let mut output = std::io::BufWriter::new(output);
+        rollup
+            .write(
+                &mut output,
+                args.group_layout,
+                &Writer::new(args.format, args.extra_stats).with_label("group"),
+                args.sort,
+                args.reverse,
+            )
+            .and_then(|_| output.flush())
+            .context(format!("Failed to write to {out_path}"))?;
+
+        println!("Full took {:?}", start.elapsed());
+        return Ok(());
+    }
 
-    let measurements = file.parse();
+    let mut measurements = finalize(stations);
+    filter::retain(&mut measurements, &args.predicates);
 
     println!("Calculations took {:?}", start.elapsed());
 
+    if let Some(report) = args.report {
+        report
+            .write(&mut std::io::stdout().lock(), &measurements)
+            .context("Failed to write report")?;
+        return Ok(());
+    }
+
+    // `parse` already returns byte order
+    if args.sort != SortOrder::Bytes || args.reverse {
+        sort::sort(&mut measurements, args.sort, args.reverse);
+    }
+
     // print the final measurements
-    let mut output = std::fs::File::create(OUT_FILE_PATH)
-        .context(format!("Failed to create {OUT_FILE_PATH}"))?;
-
-    write!(output, "{{").context(format!("Failed to write to {OUT_FILE_PATH}"))?;
-    for (i, (city, measurement)) in measurements.iter().enumerate() {
-        write!(
-            output,
-            "{}={:.1}/{:.1}/{:.1}",
-            city, measurement.min, measurement.avg, measurement.max
-        )
-        .context(format!("Failed to write to {OUT_FILE_PATH}"))?;
-
-        if i != measurements.len() - 1 {
-            write!(output, ", ").context(format!("Failed to write to {OUT_FILE_PATH}"))?;
-        }
+    let out_path = &args.output;
+    let output = std::fs::File::create(out_path).context(format!("Failed to create {out_path}"))?;
+
+    let mut output = std::io::BufWriter::new(output);
+    Writer::new(args.format, args.extra_stats)
+        .write(&mut output, &measurements)
+        .and_then(|_| output.flush())
+        .context(format!("Failed to write to {out_path}"))?;
+
+    println!("Full took {:?}", start.elapsed());
+
+    Ok(())
+}
+
+/// Second pass: write the rows outside their station's limits to `path`. The
+/// limits come from the canonical station's aggregate, looked up through the
+/// raw names the file actually contains.
+fn write_anomalies(
+    args: &args::Args,
+    file: &file::File,
+    raw_names: &[String],
+    stations: &[(String, Measurement)],
+    path: &str,
+    thresholds: &Thresholds,
+) -> anyhow::Result<()> {
+    let start = std::time::Instant::now();
+
+    let canonical: HashMap<&str, &Measurement> = stations
+        .iter()
+        .map(|(city, measurement)| (city.as_str(), measurement))
+        .collect();
+    let limits: Vec<(String, Limits)> = raw_names
+        .iter()
+        .filter_map(|name| {
+            let measurement = canonical.get(args.normalizer.canonical(name).as_ref())?;
+            Some((name.clone(), thresholds.limits(measurement)))
+        })
+        .collect();
+    let anomalies = file.anomalies(&limits);
+
+    let output = std::fs::File::create(path).context(format!("Failed to create {path}"))?;
+    let mut output = std::io::BufWriter::new(output);
+    anomaly::write(&mut output, &anomalies)
+        .and_then(|_| output.flush())
+        .context(format!("Failed to write to {path}"))?;
+
+    println!(
+        "Anomaly pass took {:?}, {} rows flagged",
+        start.elapsed(),
+        anomalies.len()
+    );
+    Ok(())
+}
+
+/// Double-send diagnostics: value histograms and estimated duplicate lines.
+fn write_diagnostics(
+    args: &args::Args,
+    file: &file::File,
+    start: std::time::Instant,
+) -> anyhow::Result<()> {
+    if args.report.is_some()
+        || args.group_by.is_some()
+        || args.first_last
+        || args.anomalies.is_some()
+        || args.window.is_some()
+        || args.schema.value_columns().len() > 1
+    {
+        bail!("--diagnostics can't be combined with other analysis modes");
+    }
+    if !args.predicates.is_empty() || !args.sort.is_by_name() {
+        bail!("--diagnostics has no statistics for --where or --sort to use");
+    }
+
+    let mut stations = args.normalizer.apply(file.diagnostics(&args.names));
+    sort::sort_by_name(&mut stations, args.sort, args.reverse);
+
+    println!("Calculations took {:?}", start.elapsed());
+    let rows: u64 = stations.iter().map(|(_, d)| d.count).sum();
+    let duplicates: u64 = stations.iter().map(|(_, d)| d.duplicates()).sum();
+    println!("{rows} rows, ~{duplicates} duplicate lines (estimated)");
+
+    let out_path = &args.output;
+    let output = std::fs::File::create(out_path).context(format!("Failed to create {out_path}"))?;
+    let mut output = std::io::BufWriter::new(output);
+    diagnostics::write_summary(&mut output, &stations, args.format)
+        .and_then(|_| output.flush())
+        .context(format!("Failed to write to {out_path}"))?;
+
+    if let Some(path) = &args.histogram {
+        let output = std::fs::File::create(path).context(format!("Failed to create {path}"))?;
+        let mut output = std::io::BufWriter::new(output);
+        diagnostics::write_histograms(&mut output, &stations)
+            .and_then(|_| output.flush())
+            .context(format!("Failed to write to {path}"))?;
     }
-    write!(output, "}}\n").context(format!("Failed to write to {OUT_FILE_PATH}"))?;
+
+    println!("Full took {:?}", start.elapsed());
+
+    Ok(())
+}
+
+/// Data-quality audit: where each station was first and last seen.
+fn write_first_last(
+    args: &args::Args,
+    file: &file::File,
+    start: std::time::Instant,
+) -> anyhow::Result<()> {
+    if args.report.is_some() || args.group_by.is_some() || args.schema.value_columns().len() > 1 {
+        bail!("--first-last needs a single value column and no --top, --bottom or --group-by");
+    }
+    if !args.sort.is_by_name() {
+        bail!("--first-last can only --sort by station name");
+    }
+
+    let mut stations = args
+        .normalizer
+        .apply(file.tracked_measurements(&args.names));
+    stations.retain(|(_, tracked)| {
+        let measurement = FinalMeasurement::new(tracked.measurement);
+        args.predicates.iter().all(|p| p.matches(&measurement))
+    });
+    sort::sort_by_name(&mut stations, args.sort, args.reverse);
+    let lines = file.line_numbers(
+        stations
+            .iter()
+            .flat_map(|(_, tracked)| [tracked.first, tracked.last])
+            .flatten()
+            .map(|sighting| sighting.offset),
+    );
+
+    println!("Calculations took {:?}", start.elapsed());
+
+    let out_path = &args.output;
+    let output = std::fs::File::create(out_path).context(format!("Failed to create {out_path}"))?;
+    let mut output = std::io::BufWriter::new(output);
+    audit::write(&mut output, &stations, &lines, args.format)
+        .and_then(|_| output.flush())
+        .context(format!("Failed to write to {out_path}"))?;
+
+    println!("Full took {:?}", start.elapsed());
+
+    Ok(())
+}
+
+/// Timestamped rows: one time series per station, one row per window.
+fn write_windows(
+    args: &args::Args,
+    file: &file::File,
+    window: Window,
+    start: std::time::Instant,
+) -> anyhow::Result<()> {
+    if args.report.is_some() || args.group_by.is_some() {
+        bail!("--top, --bottom and --group-by can't be combined with time windows");
+    }
+    if !args.sort.is_by_name() {
+        bail!("time windows can only --sort by station name");
+    }
+
+    let mut stations = args
+        .normalizer
+        .apply(file.windowed_measurements(&args.names, window));
+    for (_, series) in &mut stations {
+        series.retain(|_, measurement| {
+            let measurement = FinalMeasurement::new(*measurement);
+            args.predicates.iter().all(|p| p.matches(&measurement))
+        });
+    }
+    stations.retain(|(_, series)| !series.is_empty());
+    sort::sort_by_name(&mut stations, args.sort, args.reverse);
+
+    println!("Calculations took {:?}", start.elapsed());
+
+    let out_path = &args.output;
+    let output = std::fs::File::create(out_path).context(format!("Failed to create {out_path}"))?;
+    let mut output = std::io::BufWriter::new(output);
+    window::write(
+        &mut output,
+        &stations,
+        &Writer::new(args.format, args.extra_stats).with_label("window"),
+    )
+    .and_then(|_| output.flush())
+    .context(format!("Failed to write to {out_path}"))?;
+
+    println!("Full took {:?}", start.elapsed());
+
+    Ok(())
+}
+
+/// Several value columns: one section per metric, each filtered and sorted
+/// like the single-column output, written as one document.
+fn write_metrics(
+    args: &args::Args,
+    file: &file::File,
+    start: std::time::Instant,
+) -> anyhow::Result<()> {
+    if args.report.is_some() || args.group_by.is_some() {
+        bail!("--top, --bottom and --group-by need a single value column");
+    }
+
+    let names = args.metrics.clone().unwrap_or_else(|| file.metric_names());
+    let stations = args.normalizer.apply(file.multi_measurements(&args.names));
+
+    let sections: Vec<_> = names
+        .into_iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let stations = stations
+                .iter()
+                .filter(|(_, metrics)| metrics[i].count > 0)
+                .map(|(city, metrics)| (city.clone(), metrics[i]))
+                .collect();
+            let mut measurements = finalize(stations);
+            filter::retain(&mut measurements, &args.predicates);
+            if args.sort != SortOrder::Bytes || args.reverse {
+                sort::sort(&mut measurements, args.sort, args.reverse);
+            }
+            (name, measurements)
+        })
+        .collect();
+
+    println!("Calculations took {:?}", start.elapsed());
+
+    let out_path = &args.output;
+    let output = std::fs::File::create(out_path).context(format!("Failed to create {out_path}"))?;
+    let mut output = std::io::BufWriter::new(output);
+    Writer::new(args.format, args.extra_stats)
+        .write_sections(&mut output, "metric", &sections)
+        .and_then(|_| output.flush())
+        .context(format!("Failed to write to {out_path}"))?;
 
     println!("Full took {:?}", start.elapsed());
 
@@ -47,10 +340,11 @@ fn main() -> anyhow::Result<()> {
 
 #[cfg(test)]
 mod tests {
-    use anyhow::Context;
-
-    use crate::file;
-    use std::{fs, io::Write, path::PathBuf};
+    use one_billion_row_challenge::{
+        file,
+        output::{Format, Writer},
+    };
+    use std::{fs, path::PathBuf};
 
     #[test]
     fn test_measurement_data() {
@@ -71,27 +365,8 @@ mod tests {
             let result = file.parse();
 
             let mut actual_output = Vec::new();
-
-            write!(actual_output, "{{")
-                .context("Failed to write to {OUT_FILE_PATH}")
-                .unwrap();
-            for (i, (city, measurement)) in result.iter().enumerate() {
-                write!(
-                    actual_output,
-                    "{}={:.1}/{:.1}/{:.1}",
-                    city, measurement.min, measurement.avg, measurement.max
-                )
-                .context("Failed to write to {OUT_FILE_PATH}")
-                .unwrap();
-
-                if i != result.len() - 1 {
-                    write!(actual_output, ", ")
-                        .context("Failed to write to {OUT_FILE_PATH}")
-                        .unwrap();
-                };
-            }
-            write!(actual_output, "}}\n")
-                .context("Failed to write to {OUT_FILE_PATH}")
+            Writer::new(Format::Challenge, false)
+                .write(&mut actual_output, &result)
                 .unwrap();
 
             if actual_output != test_output {
@@ -103,8 +378,8 @@ mod tests {
                 );
             }
 
-            print!("Test passed\n");
-            print!("-----------------------------------\n");
+            println!("Test passed");
+            println!("-----------------------------------");
         }
     }
 }
@@ -1,7 +1,13 @@
+use std::collections::BTreeMap;
+
+#[derive(Clone, Copy)]
 pub struct Measurement {
-    min: i16,
-    max: i16,
+    min: i32,
+    max: i32,
     sum: i64,
+    // f64 so wide non-temperature values lose precision instead of overflowing;
+    // exact for anything the challenge can produce
+    sum_sq: f64,
     pub count: usize,
 }
 
@@ -9,19 +15,21 @@ impl Measurement {
     #[inline(always)]
     pub fn empty() -> Self {
         Self {
-            min: i16::MAX,
-            max: i16::MIN,
+            min: i32::MAX,
+            max: i32::MIN,
             sum: 0,
+            sum_sq: 0.0,
             count: 0,
         }
     }
 
     #[inline(always)]
-    pub fn new(value: i16) -> Self {
+    pub fn new(value: i32) -> Self {
         Self {
             min: value,
             max: value,
             sum: value as i64,
+            sum_sq: (value as f64) * (value as f64),
             count: 1,
         }
     }
@@ -32,13 +40,15 @@ impl Measurement {
             min: other.min,
             max: other.max,
             sum: other.sum,
+            sum_sq: other.sum_sq,
             count: other.count,
         }
     }
 
     #[inline(always)]
-    pub fn add(&mut self, value: i16) {
+    pub fn add(&mut self, value: i32) {
         self.sum += value as i64;
+        self.sum_sq += (value as f64) * (value as f64);
         self.count += 1;
 
         if value < self.min {
@@ -52,6 +62,7 @@ impl Measurement {
     #[inline(always)]
     pub fn merge(&mut self, other: &Measurement) {
         self.sum += other.sum;
+        self.sum_sq += other.sum_sq;
         self.count += other.count;
 
         if other.min < self.min {
@@ -62,13 +73,220 @@ impl Measurement {
         }
     }
 
+    /// The mean, rounded to tenths. A negative mean that rounds to zero is
+    /// `-0.0`, which the challenge output prints as `-0.0`.
     #[inline(always)]
     pub fn avg(&self) -> f32 {
-        // Calculate raw average
-        let avg = (self.sum as f32) / (self.count as f32) / 10.0;
+        let avg = int_to_float(self.avg_tenths());
+        if self.sum < 0 { -avg.abs() } else { avg }
+    }
+
+    #[inline(always)]
+    pub fn min_tenths(&self) -> i32 {
+        self.min
+    }
+
+    #[inline(always)]
+    pub fn max_tenths(&self) -> i32 {
+        self.max
+    }
+
+    /// Average in tenths, rounded half away from zero using integer math only.
+    /// 0 for an empty aggregate.
+    #[inline(always)]
+    pub fn avg_tenths(&self) -> i64 {
+        if self.count == 0 {
+            return 0;
+        }
+        let count = self.count as i64;
+        let rounded = (2 * self.sum.abs() + count) / (2 * count);
+
+        if self.sum < 0 { -rounded } else { rounded }
+    }
+
+    /// Unrounded mean, in tenths.
+    #[inline(always)]
+    pub fn mean_tenths(&self) -> f64 {
+        self.sum as f64 / self.count as f64
+    }
+
+    /// Population standard deviation, in tenths. NaN if the squares were not
+    /// tracked, see [`Tally`].
+    #[inline(always)]
+    pub fn stddev_tenths(&self) -> f64 {
+        let mean = self.mean_tenths();
+        // rounding can make the variance slightly negative; `f64::max` would also turn NaN into 0
+        let variance = self.sum_sq / self.count as f64 - mean * mean;
+        let variance = if variance < 0.0 { 0.0 } else { variance };
+
+        variance.sqrt()
+    }
+
+    /// Population standard deviation, in degrees.
+    #[inline(always)]
+    pub fn stddev(&self) -> f32 {
+        (self.stddev_tenths() / 10.0) as f32
+    }
+}
+
+impl Default for Measurement {
+    #[inline(always)]
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl From<Tally> for Measurement {
+    /// The tally never saw the squares, so the standard deviation is NaN.
+    #[inline(always)]
+    fn from(tally: Tally) -> Self {
+        Self {
+            min: tally.min,
+            max: tally.max,
+            sum: tally.sum,
+            sum_sq: f64::NAN,
+            count: tally.count,
+        }
+    }
+}
+
+/// [`Measurement`] without the sum of squares: the challenge path's
+/// aggregate, kept to 24 bytes and integer-only updates. Modes that report
+/// a standard deviation aggregate into a [`Measurement`] instead.
+#[derive(Clone, Copy)]
+pub struct Tally {
+    min: i32,
+    max: i32,
+    sum: i64,
+    count: usize,
+}
+
+impl Tally {
+    #[inline(always)]
+    pub fn empty() -> Self {
+        Self {
+            min: i32::MAX,
+            max: i32::MIN,
+            sum: 0,
+            count: 0,
+        }
+    }
+
+    #[inline(always)]
+    pub fn new(value: i32) -> Self {
+        Self {
+            min: value,
+            max: value,
+            sum: value as i64,
+            count: 1,
+        }
+    }
+
+    #[inline(always)]
+    pub fn add(&mut self, value: i32) {
+        self.sum += value as i64;
+        self.count += 1;
+
+        if value < self.min {
+            self.min = value;
+        }
+        if value > self.max {
+            self.max = value;
+        }
+    }
+
+    #[inline(always)]
+    pub fn merge(&mut self, other: &Tally) {
+        self.sum += other.sum;
+        self.count += other.count;
+
+        if other.min < self.min {
+            self.min = other.min;
+        }
+        if other.max > self.max {
+            self.max = other.max;
+        }
+    }
+}
+
+impl Default for Tally {
+    #[inline(always)]
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+/// Per-key state kept in the parser's hash map, combined across chunks with `merge`.
+pub trait Aggregate: Default {
+    fn merge(&mut self, other: &Self);
+}
+
+/// An [`Aggregate`] built one value at a time, as the parsers insert rows.
+pub trait Accumulate: Aggregate {
+    fn new(value: i32) -> Self;
+    fn add(&mut self, value: i32);
+}
+
+impl Aggregate for Measurement {
+    #[inline(always)]
+    fn merge(&mut self, other: &Self) {
+        Measurement::merge(self, other);
+    }
+}
+
+impl Accumulate for Measurement {
+    #[inline(always)]
+    fn new(value: i32) -> Self {
+        Measurement::new(value)
+    }
+
+    #[inline(always)]
+    fn add(&mut self, value: i32) {
+        Measurement::add(self, value);
+    }
+}
+
+impl Aggregate for Tally {
+    #[inline(always)]
+    fn merge(&mut self, other: &Self) {
+        Tally::merge(self, other);
+    }
+}
+
+impl Accumulate for Tally {
+    #[inline(always)]
+    fn new(value: i32) -> Self {
+        Tally::new(value)
+    }
 
-        // Round to one decimal place
-        (avg * 10.0).round() / 10.0
+    #[inline(always)]
+    fn add(&mut self, value: i32) {
+        Tally::add(self, value);
+    }
+}
+
+/// One independent [`Measurement`] per value column. Columns a row had no
+/// value for are left untouched, so their counts can differ.
+impl Aggregate for Vec<Measurement> {
+    fn merge(&mut self, other: &Self) {
+        if self.len() < other.len() {
+            self.resize_with(other.len(), Measurement::empty);
+        }
+        for (measurement, other) in self.iter_mut().zip(other) {
+            measurement.merge(other);
+        }
+    }
+}
+
+/// One [`Measurement`] per time window, keyed by the window start. Windows
+/// present in both sides are merged, so chunks can be combined in any order.
+impl<K: Ord + Copy> Aggregate for BTreeMap<K, Measurement> {
+    fn merge(&mut self, other: &Self) {
+        for (window, measurement) in other {
+            self.entry(*window)
+                .or_insert_with(Measurement::empty)
+                .merge(measurement);
+        }
     }
 }
 
@@ -76,12 +294,28 @@ pub struct FinalMeasurement {
     pub min: f32,
     pub max: f32,
     pub avg: f32,
+    pub stddev: f32,
+    pub count: usize,
+    /// The integer aggregate the floats were derived from, for exact formatting.
+    pub tenths: Measurement,
 }
 
 impl FinalMeasurement {
     #[inline(always)]
-    pub fn new(min: f32, max: f32, avg: f32) -> Self {
-        Self { min, max, avg }
+    pub fn new(measurement: Measurement) -> Self {
+        Self {
+            min: int_to_float(measurement.min as i64),
+            max: int_to_float(measurement.max as i64),
+            avg: measurement.avg(),
+            stddev: measurement.stddev(),
+            count: measurement.count,
+            tenths: measurement,
+        }
+    }
+
+    #[inline(always)]
+    pub fn range(&self) -> f32 {
+        self.max - self.min
     }
 }
 
@@ -90,13 +324,20 @@ fn int_to_float(value: i64) -> f32 {
     (value as f32) / 10.0
 }
 
-impl Into<FinalMeasurement> for Measurement {
+impl From<Measurement> for FinalMeasurement {
     #[inline(always)]
-    fn into(self) -> FinalMeasurement {
-        FinalMeasurement::new(
-            int_to_float(self.min as i64),
-            int_to_float(self.max as i64),
-            self.avg(), // avg now returns properly scaled f32
-        )
+    fn from(measurement: Measurement) -> Self {
+        FinalMeasurement::new(measurement)
     }
 }
+
+/// Convert merged aggregates into the final, byte-ordered station list.
+pub fn finalize(measurements: Vec<(String, Measurement)>) -> Vec<(String, FinalMeasurement)> {
+    let mut results: Vec<(String, FinalMeasurement)> = measurements
+        .into_iter()
+        .map(|(city, measurement)| (city, measurement.into()))
+        .collect();
+
+    results.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    results
+}
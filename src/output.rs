@@ -0,0 +1,634 @@
+use std::{io::Write, str::FromStr};
+
+use anyhow::anyhow;
+use unicode_width::UnicodeWidthStr;
+
+use crate::measurement::FinalMeasurement;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// The challenge's `{Abha=-23.0/18.0/59.2, ...}` line.
+    #[default]
+    Challenge,
+    Json,
+    Csv,
+    Tsv,
+    Markdown,
+    /// Aligned plain-text table for terminals.
+    Table,
+    #[cfg(feature = "arrow")]
+    Arrow,
+    #[cfg(feature = "arrow")]
+    Parquet,
+}
+
+impl Format {
+    /// Whether the format is plain text, where `## name` headings can separate
+    /// several tables and free-form lines can follow them.
+    pub fn is_text(&self) -> bool {
+        matches!(self, Format::Challenge | Format::Markdown | Format::Table)
+    }
+
+    pub fn default_path(&self) -> &'static str {
+        match self {
+            Format::Challenge => crate::OUT_FILE_PATH,
+            Format::Json => "./output.json",
+            Format::Csv => "./output.csv",
+            Format::Tsv => "./output.tsv",
+            Format::Markdown => "./output.md",
+            Format::Table => "./output.txt",
+            #[cfg(feature = "arrow")]
+            Format::Arrow => "./output.arrow",
+            #[cfg(feature = "arrow")]
+            Format::Parquet => "./output.parquet",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "challenge" | "out" => Ok(Format::Challenge),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            "tsv" => Ok(Format::Tsv),
+            "markdown" | "md" => Ok(Format::Markdown),
+            "table" | "text" => Ok(Format::Table),
+            #[cfg(feature = "arrow")]
+            "arrow" | "ipc" => Ok(Format::Arrow),
+            #[cfg(feature = "arrow")]
+            "parquet" => Ok(Format::Parquet),
+            #[cfg(not(feature = "arrow"))]
+            "arrow" | "ipc" | "parquet" => Err(anyhow!(
+                "{s} output requires building with `--features arrow`"
+            )),
+            _ => Err(anyhow!("unknown output format: {s}")),
+        }
+    }
+}
+
+/// Writes the final measurements in any of the supported [`Format`]s.
+pub struct Writer {
+    format: Format,
+    extra_stats: bool,
+    label: &'static str,
+}
+
+impl Writer {
+    /// `extra_stats` adds the `stddev` and `range` statistics to every station.
+    #[inline(always)]
+    pub fn new(format: Format, extra_stats: bool) -> Self {
+        Self {
+            format,
+            extra_stats,
+            label: "station",
+        }
+    }
+
+    /// Header of the name column in the tabular formats, `station` by default.
+    #[inline(always)]
+    pub fn with_label(mut self, label: &'static str) -> Self {
+        self.label = label;
+        self
+    }
+
+    #[inline(always)]
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn write(
+        &self,
+        output: &mut impl Write,
+        measurements: &[(String, FinalMeasurement)],
+    ) -> std::io::Result<()> {
+        match self.format {
+            Format::Challenge => self.write_challenge(output, measurements),
+            Format::Json => {
+                self.write_json_object(output, measurements, "")?;
+                output.write_all(b"\n")
+            }
+            Format::Csv => {
+                self.write_delimited(output, None, rows(measurements, None), b',', b"\r\n")
+            }
+            Format::Tsv => {
+                self.write_delimited(output, None, rows(measurements, None), b'\t', b"\n")
+            }
+            Format::Markdown => self.write_table(output, measurements, true),
+            Format::Table => self.write_table(output, measurements, false),
+            #[cfg(feature = "arrow")]
+            Format::Arrow | Format::Parquet => {
+                self.write_batch(output, crate::arrow::to_record_batch(measurements))
+            }
+        }
+    }
+
+    /// Several named tables in one document: a `## name` heading before each
+    /// in the text formats, an object per table in JSON, and the name in a
+    /// leading `section` column of a single table in every other format.
+    pub fn write_sections(
+        &self,
+        output: &mut impl Write,
+        section: &str,
+        sections: &[(String, Vec<(String, FinalMeasurement)>)],
+    ) -> std::io::Result<()> {
+        let all_rows = || {
+            sections
+                .iter()
+                .flat_map(|(name, measurements)| rows(measurements, Some(name)))
+        };
+
+        match self.format {
+            Format::Challenge | Format::Markdown | Format::Table => {
+                for (i, (name, measurements)) in sections.iter().enumerate() {
+                    let separator = if i == 0 { "" } else { "\n" };
+                    writeln!(output, "{separator}## {name}")?;
+                    self.write(output, measurements)?;
+                }
+                Ok(())
+            }
+            Format::Json => {
+                output.write_all(b"{")?;
+                for (i, (name, measurements)) in sections.iter().enumerate() {
+                    if i != 0 {
+                        output.write_all(b",")?;
+                    }
+                    output.write_all(b"\n  ")?;
+                    write_json_string(output, name)?;
+                    output.write_all(b": ")?;
+                    self.write_json_object(output, measurements, "  ")?;
+                }
+                if !sections.is_empty() {
+                    output.write_all(b"\n")?;
+                }
+                output.write_all(b"}\n")
+            }
+            Format::Csv => self.write_delimited(output, Some(section), all_rows(), b',', b"\r\n"),
+            Format::Tsv => self.write_delimited(output, Some(section), all_rows(), b'\t', b"\n"),
+            #[cfg(feature = "arrow")]
+            Format::Arrow | Format::Parquet => self.write_batch(
+                output,
+                crate::arrow::sections_to_record_batch(section, self.label, sections),
+            ),
+        }
+    }
+
+    #[cfg(feature = "arrow")]
+    fn write_batch(
+        &self,
+        output: &mut impl Write,
+        batch: Result<arrow_array::RecordBatch, arrow_schema::ArrowError>,
+    ) -> std::io::Result<()> {
+        let batch = batch.map_err(std::io::Error::other)?;
+        match self.format {
+            Format::Parquet => crate::arrow::write_parquet(output, &batch),
+            _ => crate::arrow::write_ipc(output, &batch),
+        }
+        .map_err(std::io::Error::other)
+    }
+
+    fn columns(&self) -> Vec<&'static str> {
+        let mut columns = vec![self.label, "min", "mean", "max", "count"];
+        if self.extra_stats {
+            columns.extend(["stddev", "range"]);
+        }
+        columns
+    }
+
+    /// The numeric columns of one station, formatted the same way for every tabular format.
+    fn cells(&self, measurement: &FinalMeasurement) -> Vec<String> {
+        let mut cells = vec![
+            format!("{:.1}", measurement.min),
+            format!("{:.1}", measurement.avg),
+            format!("{:.1}", measurement.max),
+            measurement.count.to_string(),
+        ];
+        if self.extra_stats {
+            cells.push(format!("{:.2}", measurement.stddev));
+            cells.push(format!("{:.1}", measurement.range()));
+        }
+        cells
+    }
+
+    /// Formats the whole line into one buffer from the integer tenths and
+    /// hands it to `output` in a single write.
+    fn write_challenge(
+        &self,
+        output: &mut impl Write,
+        measurements: &[(String, FinalMeasurement)],
+    ) -> std::io::Result<()> {
+        let capacity = measurements
+            .iter()
+            .map(|(city, _)| city.len() + 24)
+            .sum::<usize>();
+        let mut buffer = Vec::with_capacity(capacity + 3);
+
+        buffer.push(b'{');
+        for (i, (city, measurement)) in measurements.iter().enumerate() {
+            if i != 0 {
+                buffer.extend_from_slice(b", ");
+            }
+            buffer.extend_from_slice(city.as_bytes());
+            buffer.push(b'=');
+            push_tenths(&mut buffer, measurement.tenths.min_tenths() as i64);
+            buffer.push(b'/');
+            if measurement.avg == 0.0 && measurement.avg.is_sign_negative() {
+                buffer.push(b'-');
+            }
+            push_tenths(&mut buffer, measurement.tenths.avg_tenths());
+            buffer.push(b'/');
+            push_tenths(&mut buffer, measurement.tenths.max_tenths() as i64);
+        }
+        buffer.extend_from_slice(b"}\n");
+
+        output.write_all(&buffer)
+    }
+
+    /// Stream the measurements as a JSON object keyed by station, one station
+    /// per line, each line indented by `indent` and two more spaces.
+    fn write_json_object(
+        &self,
+        output: &mut impl Write,
+        measurements: &[(String, FinalMeasurement)],
+        indent: &str,
+    ) -> std::io::Result<()> {
+        output.write_all(b"{")?;
+        for (i, (city, measurement)) in measurements.iter().enumerate() {
+            if i != 0 {
+                output.write_all(b",")?;
+            }
+            write!(output, "\n{indent}  ")?;
+            write_json_string(output, city)?;
+            write!(
+                output,
+                ": {{\"min\": {:.1}, \"mean\": {:.1}, \"max\": {:.1}, \"count\": {}",
+                measurement.min, measurement.avg, measurement.max, measurement.count
+            )?;
+            if self.extra_stats {
+                write!(
+                    output,
+                    ", \"stddev\": {:.2}, \"range\": {:.1}",
+                    measurement.stddev,
+                    measurement.range()
+                )?;
+            }
+            output.write_all(b"}")?;
+        }
+        if !measurements.is_empty() {
+            write!(output, "\n{indent}")?;
+        }
+        output.write_all(b"}")
+    }
+
+    /// CSV/TSV with a header row, quoting names per RFC 4180. A `section`
+    /// column, if any, comes first and holds each row's section name.
+    fn write_delimited<'m>(
+        &self,
+        output: &mut impl Write,
+        section: Option<&str>,
+        rows: impl Iterator<Item = Row<'m>>,
+        delimiter: u8,
+        line_end: &[u8],
+    ) -> std::io::Result<()> {
+        let columns: Vec<&str> = section.into_iter().chain(self.columns()).collect();
+        output.write_all(columns.join(&(delimiter as char).to_string()).as_bytes())?;
+        output.write_all(line_end)?;
+
+        for (section, (city, measurement)) in rows {
+            if let Some(section) = section {
+                write_delimited_field(output, section, delimiter)?;
+                output.write_all(&[delimiter])?;
+            }
+            write_delimited_field(output, city, delimiter)?;
+            for cell in self.cells(measurement) {
+                output.write_all(&[delimiter])?;
+                output.write_all(cell.as_bytes())?;
+            }
+            output.write_all(line_end)?;
+        }
+
+        Ok(())
+    }
+
+    /// Markdown or plain-text table, padded by display width so wide characters line up.
+    fn write_table(
+        &self,
+        output: &mut impl Write,
+        measurements: &[(String, FinalMeasurement)],
+        markdown: bool,
+    ) -> std::io::Result<()> {
+        let rows: Vec<(String, Vec<String>)> = measurements
+            .iter()
+            .map(|(city, measurement)| {
+                let city = if markdown {
+                    city.replace('|', "\\|")
+                } else {
+                    city.clone()
+                };
+                (city, self.cells(measurement))
+            })
+            .collect();
+
+        let columns = self.columns();
+        let mut widths: Vec<usize> = columns.iter().map(|c| c.len()).collect();
+        if markdown {
+            // the delimiter row needs at least three dashes per column
+            widths.iter_mut().for_each(|w| *w = (*w).max(3));
+        }
+        for (city, cells) in &rows {
+            widths[0] = widths[0].max(display_width(city));
+            for (width, cell) in widths[1..].iter_mut().zip(cells) {
+                *width = (*width).max(cell.len());
+            }
+        }
+
+        let (start, separator, end) = if markdown {
+            ("| ", " | ", " |")
+        } else {
+            ("", "  ", "")
+        };
+
+        let header: Vec<String> = columns.iter().map(|c| c.to_string()).collect();
+        write_row(output, &widths, &header[0], &header[1..], start, separator)?;
+        writeln!(output, "{end}")?;
+
+        if markdown {
+            write!(output, "|:{}", "-".repeat(widths[0] + 1))?;
+            for width in &widths[1..] {
+                write!(output, "|{}:", "-".repeat(width + 1))?;
+            }
+            writeln!(output, "|")?;
+        }
+
+        for (city, cells) in &rows {
+            write_row(output, &widths, city, cells, start, separator)?;
+            writeln!(output, "{end}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A row of [`Writer::write_sections`]: the name of its section, if there is
+/// one, and the station.
+type Row<'m> = (Option<&'m str>, &'m (String, FinalMeasurement));
+
+#[inline(always)]
+fn rows<'m>(
+    measurements: &'m [(String, FinalMeasurement)],
+    section: Option<&'m str>,
+) -> impl Iterator<Item = Row<'m>> {
+    measurements.iter().map(move |row| (section, row))
+}
+
+/// Append `value` tenths as `[-]D.D`, matching `{:.1}` without going through floats.
+#[inline(always)]
+fn push_tenths(buffer: &mut Vec<u8>, value: i64) {
+    if value < 0 {
+        buffer.push(b'-');
+    }
+    let value = value.unsigned_abs();
+
+    let mut digits = [0u8; 20];
+    let mut i = digits.len();
+    let mut whole = value / 10;
+    loop {
+        i -= 1;
+        digits[i] = b'0' + (whole % 10) as u8;
+        whole /= 10;
+        if whole == 0 {
+            break;
+        }
+    }
+
+    buffer.extend_from_slice(&digits[i..]);
+    buffer.push(b'.');
+    buffer.push(b'0' + (value % 10) as u8);
+}
+
+/// `value` tenths as `[-]D.D`.
+pub(crate) fn format_tenths(value: i64) -> String {
+    let mut buffer = Vec::with_capacity(24);
+    push_tenths(&mut buffer, value);
+    String::from_utf8(buffer).unwrap()
+}
+
+/// Number of terminal columns `value` occupies.
+#[inline(always)]
+pub fn display_width(value: &str) -> usize {
+    UnicodeWidthStr::width(value)
+}
+
+/// Left-align the station and right-align every numeric cell.
+fn write_row(
+    output: &mut impl Write,
+    widths: &[usize],
+    city: &str,
+    cells: &[String],
+    start: &str,
+    separator: &str,
+) -> std::io::Result<()> {
+    let padding = widths[0] - display_width(city);
+    write!(output, "{start}{city}{:padding$}", "")?;
+    for (cell, width) in cells.iter().zip(&widths[1..]) {
+        write!(output, "{separator}{cell:>width$}")?;
+    }
+    Ok(())
+}
+
+/// Write rows that don't fit [`Writer`]'s fixed columns: CSV and TSV with a
+/// header row, an aligned plain-text table for every other format.
+pub(crate) fn write_rows(
+    output: &mut impl Write,
+    header: &[&str],
+    rows: &[(&str, Vec<String>)],
+    format: Format,
+) -> std::io::Result<()> {
+    let delimiter = match format {
+        Format::Csv => Some((b',', "\r\n")),
+        Format::Tsv => Some((b'\t', "\n")),
+        _ => None,
+    };
+    if let Some((delimiter, line_end)) = delimiter {
+        let separator = (delimiter as char).to_string();
+        write!(output, "{}{line_end}", header.join(&separator))?;
+        for (name, cells) in rows {
+            write_delimited_field(output, name, delimiter)?;
+            write!(output, "{separator}{}{line_end}", cells.join(&separator))?;
+        }
+        return Ok(());
+    }
+
+    let mut widths: Vec<usize> = header.iter().map(|c| c.len()).collect();
+    for (name, cells) in rows {
+        widths[0] = widths[0].max(display_width(name));
+        for (width, cell) in widths[1..].iter_mut().zip(cells) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let header: Vec<String> = header.iter().map(|c| c.to_string()).collect();
+    write_row(output, &widths, &header[0], &header[1..], "", "  ")?;
+    writeln!(output)?;
+    for (name, cells) in rows {
+        write_row(output, &widths, name, cells, "", "  ")?;
+        writeln!(output)?;
+    }
+
+    Ok(())
+}
+
+/// Write one CSV/TSV field, quoting it when it contains the delimiter, a quote or a line break.
+pub(crate) fn write_delimited_field(
+    output: &mut impl Write,
+    value: &str,
+    delimiter: u8,
+) -> std::io::Result<()> {
+    let needs_quotes = value
+        .bytes()
+        .any(|b| b == delimiter || b == b'"' || b == b'\n' || b == b'\r');
+    if !needs_quotes {
+        return output.write_all(value.as_bytes());
+    }
+
+    output.write_all(b"\"")?;
+    output.write_all(value.replace('"', "\"\"").as_bytes())?;
+    output.write_all(b"\"")
+}
+
+/// Write `value` as a quoted JSON string, escaping quotes, backslashes and control characters.
+fn write_json_string(output: &mut impl Write, value: &str) -> std::io::Result<()> {
+    output.write_all(b"\"")?;
+
+    let bytes = value.as_bytes();
+    let mut start = 0;
+    for (i, &byte) in bytes.iter().enumerate() {
+        let escape: &[u8] = match byte {
+            b'"' => b"\\\"",
+            b'\\' => b"\\\\",
+            b'\n' => b"\\n",
+            b'\r' => b"\\r",
+            b'\t' => b"\\t",
+            0x08 => b"\\b",
+            0x0C => b"\\f",
+            0x00..=0x1F => b"",
+            _ => continue,
+        };
+
+        output.write_all(&bytes[start..i])?;
+        if escape.is_empty() {
+            write!(output, "\\u{byte:04x}")?;
+        } else {
+            output.write_all(escape)?;
+        }
+        start = i + 1;
+    }
+    output.write_all(&bytes[start..])?;
+
+    output.write_all(b"\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Format, Writer, push_tenths, write_delimited_field, write_json_string};
+    use crate::measurement::{FinalMeasurement, Measurement};
+
+    fn sections() -> Vec<(String, Vec<(String, FinalMeasurement)>)> {
+        let mut abha = Measurement::new(-12);
+        abha.add(34);
+        vec![
+            (
+                "temperature".to_string(),
+                vec![("Abha".to_string(), abha.into())],
+            ),
+            ("humidity".to_string(), vec![]),
+            (
+                "wind".to_string(),
+                vec![("Oslo, NO".to_string(), Measurement::new(50).into())],
+            ),
+        ]
+    }
+
+    fn write_sections(format: Format) -> String {
+        let mut actual = Vec::new();
+        Writer::new(format, false)
+            .write_sections(&mut actual, "metric", &sections())
+            .unwrap();
+        String::from_utf8(actual).unwrap()
+    }
+
+    #[test]
+    fn test_tenths_match_float_formatting() {
+        for value in -9999..=9999 {
+            let mut actual = Vec::new();
+            push_tenths(&mut actual, value);
+
+            assert_eq!(
+                std::str::from_utf8(&actual).unwrap(),
+                format!("{:.1}", value as f64 / 10.0)
+            );
+        }
+    }
+
+    #[test]
+    fn test_json_string_escaping() {
+        let mut actual = Vec::new();
+        write_json_string(&mut actual, "São \"Paulo\"\\\n\t\u{1}").unwrap();
+
+        assert_eq!(
+            std::str::from_utf8(&actual).unwrap(),
+            r#""São \"Paulo\"\\\n\t\u0001""#
+        );
+    }
+
+    #[test]
+    fn test_csv_field_quoting() {
+        let mut actual = Vec::new();
+        for field in ["Abha", "Washington, D.C.", "The \"Dock\""] {
+            write_delimited_field(&mut actual, field, b',').unwrap();
+            actual.push(b'\n');
+        }
+
+        assert_eq!(
+            std::str::from_utf8(&actual).unwrap(),
+            "Abha\n\"Washington, D.C.\"\n\"The \"\"Dock\"\"\"\n"
+        );
+    }
+
+    #[test]
+    fn test_sections_are_headings_in_text() {
+        assert_eq!(
+            write_sections(Format::Challenge),
+            "## temperature\n{Abha=-1.2/1.1/3.4}\n\n## humidity\n{}\n\n## wind\n{Oslo, NO=5.0/5.0/5.0}\n"
+        );
+    }
+
+    #[test]
+    fn test_sections_are_one_json_object() {
+        assert_eq!(
+            write_sections(Format::Json),
+            r#"{
+  "temperature": {
+    "Abha": {"min": -1.2, "mean": 1.1, "max": 3.4, "count": 2}
+  },
+  "humidity": {},
+  "wind": {
+    "Oslo, NO": {"min": 5.0, "mean": 5.0, "max": 5.0, "count": 1}
+  }
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_sections_are_one_csv_table() {
+        assert_eq!(
+            write_sections(Format::Csv),
+            "metric,station,min,mean,max,count\r\n\
+             temperature,Abha,-1.2,1.1,3.4,2\r\n\
+             wind,\"Oslo, NO\",5.0,5.0,5.0,1\r\n"
+        );
+    }
+}
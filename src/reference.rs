@@ -0,0 +1,212 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, anyhow, bail};
+
+/// Per-station aggregate of the reference implementation, in tenths of a degree.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stats {
+    pub min: i64,
+    pub max: i64,
+    pub sum: i64,
+    pub count: i64,
+}
+
+impl Stats {
+    /// The mean in tenths, rounded half away from zero.
+    pub fn mean(&self) -> i64 {
+        let (quotient, remainder) = (self.sum.abs() / self.count, self.sum.abs() % self.count);
+        let rounded = quotient + i64::from(2 * remainder >= self.count);
+        if self.sum < 0 { -rounded } else { rounded }
+    }
+}
+
+/// Aggregates challenge input the slow, obvious way: a `BTreeMap` keyed by
+/// station and `str::parse::<f64>` for the values. The optimized parsers are
+/// checked against it, so it favours being easy to read over being fast.
+pub fn aggregate(input: &str) -> anyhow::Result<BTreeMap<String, Stats>> {
+    let mut stations = BTreeMap::<String, Stats>::new();
+
+    for (index, line) in input.lines().enumerate() {
+        let line_number = index + 1;
+        let (station, value) = line
+            .split_once(';')
+            .ok_or_else(|| anyhow!("line {line_number}: missing ';' in {line:?}"))?;
+        let value: f64 = value
+            .parse()
+            .with_context(|| format!("line {line_number}: invalid temperature {value:?}"))?;
+        if !value.is_finite() {
+            bail!("line {line_number}: invalid temperature {value}");
+        }
+
+        // Every challenge value has exactly one decimal, so this is exact.
+        let tenths = (value * 10.0).round() as i64;
+        stations
+            .entry(station.to_string())
+            .and_modify(|stats| {
+                stats.min = stats.min.min(tenths);
+                stats.max = stats.max.max(tenths);
+                stats.sum += tenths;
+                stats.count += 1;
+            })
+            .or_insert(Stats {
+                min: tenths,
+                max: tenths,
+                sum: tenths,
+                count: 1,
+            });
+    }
+
+    Ok(stations)
+}
+
+/// The challenge output for `stations`: `{name=min/mean/max, ...}` followed by
+/// a newline, in byte order of the names.
+pub fn format(stations: &BTreeMap<String, Stats>) -> String {
+    let entries = stations
+        .iter()
+        .map(|(station, stats)| {
+            format!(
+                "{station}={}/{}/{}",
+                decimal(stats.min),
+                mean(stats),
+                decimal(stats.max)
+            )
+        })
+        .collect::<Vec<_>>();
+    format!("{{{}}}\n", entries.join(", "))
+}
+
+/// A negative mean that rounds to zero prints as `-0.0`, like a float would.
+fn mean(stats: &Stats) -> String {
+    match stats.mean() {
+        0 if stats.sum < 0 => "-0.0".to_string(),
+        mean => decimal(mean),
+    }
+}
+
+fn decimal(tenths: i64) -> String {
+    let sign = if tenths < 0 { "-" } else { "" };
+    format!("{sign}{}.{}", tenths.abs() / 10, tenths.abs() % 10)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fmt::Write, fs, path::PathBuf, process};
+
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    use super::{aggregate, format};
+    use crate::{
+        file::File,
+        output::{Format, Writer},
+    };
+
+    /// Code points names are drawn from: mostly ASCII, then 2, 3 and 4-byte UTF-8.
+    const NAME_CHARS: [(u32, u32); 5] = [
+        (0x20, 0x3a),
+        (0x3c, 0x7e),
+        (0xc0, 0x24f),
+        (0x4e00, 0x9fff),
+        (0x1f300, 0x1f64f),
+    ];
+
+    fn random_name(rng: &mut StdRng) -> String {
+        let target = rng.random_range(1..=100);
+        let mut name = String::new();
+        loop {
+            let (lo, hi) = NAME_CHARS[rng.random_range(0..NAME_CHARS.len())];
+            let c = char::from_u32(rng.random_range(lo..=hi)).unwrap();
+            if name.len() + c.len_utf8() > target {
+                break;
+            }
+            name.push(c);
+        }
+        if name.is_empty() {
+            name.push('x');
+        }
+        name
+    }
+
+    fn random_input(seed: u64) -> String {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let stations = (0..rng.random_range(1..=1000))
+            .map(|_| random_name(&mut rng))
+            .collect::<Vec<_>>();
+        let mut input = String::new();
+        for _ in 0..rng.random_range(0..20_000) {
+            let station = &stations[rng.random_range(0..stations.len())];
+            let tenths: i32 = rng.random_range(-999..=999);
+            let sign = if tenths < 0 { "-" } else { "" };
+            let (whole, fraction) = (tenths.abs() / 10, tenths.abs() % 10);
+            writeln!(input, "{station};{sign}{whole}.{fraction}").unwrap();
+        }
+        input
+    }
+
+    fn parse_optimized(input: &str, seed: u64) -> String {
+        // one directory per process, so concurrent test runs never share a file
+        let dir = std::env::temp_dir().join(format!("1brc-test-reference-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{seed}.txt"));
+        fs::write(&path, input).unwrap();
+        let measurements = File::open(path.to_str().unwrap()).unwrap().parse();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let mut output = Vec::new();
+        Writer::new(Format::Challenge, false)
+            .write(&mut output, &measurements)
+            .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_reference() {
+        let input = "b;-0.1\na;1.5\nb;-0.2\na;-3.0\né;0.0\n";
+        assert_eq!(
+            format(&aggregate(input).unwrap()),
+            "{a=-3.0/-0.8/1.5, b=-0.2/-0.2/-0.1, é=0.0/0.0/0.0}\n"
+        );
+        assert_eq!(format(&aggregate("").unwrap()), "{}\n");
+    }
+
+    #[test]
+    fn test_reference_errors() {
+        let error = aggregate("a;1.0\nb\n").unwrap_err();
+        assert!(error.to_string().contains("line 2"), "{error}");
+        let error = aggregate("a;1.0\na;1.0\na;x\n").unwrap_err();
+        assert!(error.to_string().contains("line 3"), "{error}");
+    }
+
+    #[test]
+    fn test_reference_matches_test_data() {
+        let test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests");
+        for file in fs::read_dir(test_dir).unwrap() {
+            let path = file.unwrap().path();
+            if path.extension().is_none_or(|extension| extension != "txt") {
+                continue;
+            }
+            let input = fs::read_to_string(&path).unwrap();
+            let expected = fs::read_to_string(path.with_extension("out")).unwrap();
+            assert_eq!(
+                format(&aggregate(&input).unwrap()),
+                expected,
+                "{}",
+                path.display()
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_matches_reference() {
+        for seed in 0..32 {
+            let input = random_input(seed);
+            let expected = format(&aggregate(&input).unwrap());
+            assert_eq!(
+                parse_optimized(&input, seed),
+                expected,
+                "seed {seed}, {} bytes",
+                input.len()
+            );
+        }
+    }
+}
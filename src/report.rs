@@ -0,0 +1,208 @@
+use std::{cmp::Ordering, io::Write, str::FromStr};
+
+use anyhow::anyhow;
+
+use crate::{measurement::FinalMeasurement, output::display_width};
+
+/// Statistic a station can be ranked by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Statistic {
+    Min,
+    Max,
+    Avg,
+    Count,
+    Range,
+    StdDev,
+}
+
+impl Statistic {
+    #[inline(always)]
+    pub fn value(&self, measurement: &FinalMeasurement) -> f64 {
+        match self {
+            Statistic::Min => measurement.min as f64,
+            Statistic::Max => measurement.max as f64,
+            Statistic::Avg => measurement.avg as f64,
+            Statistic::Count => measurement.count as f64,
+            Statistic::Range => measurement.range() as f64,
+            Statistic::StdDev => measurement.stddev as f64,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Statistic::Min => "min",
+            Statistic::Max => "max",
+            Statistic::Avg => "avg",
+            Statistic::Count => "count",
+            Statistic::Range => "range",
+            Statistic::StdDev => "stddev",
+        }
+    }
+}
+
+impl FromStr for Statistic {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "min" => Ok(Statistic::Min),
+            "max" => Ok(Statistic::Max),
+            "avg" | "mean" => Ok(Statistic::Avg),
+            "count" => Ok(Statistic::Count),
+            "range" => Ok(Statistic::Range),
+            "stddev" | "std" => Ok(Statistic::StdDev),
+            _ => Err(anyhow!("unknown statistic: {s}")),
+        }
+    }
+}
+
+/// Which end of the ranking a report shows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rank {
+    Top,
+    Bottom,
+}
+
+pub struct Report {
+    pub rank: Rank,
+    pub statistic: Statistic,
+    pub n: usize,
+}
+
+impl Report {
+    #[inline(always)]
+    pub fn new(rank: Rank, statistic: Statistic, n: usize) -> Self {
+        Self { rank, statistic, n }
+    }
+
+    /// Select the `n` best-ranked stations without sorting the whole list.
+    /// Ties are broken by station name so the output is deterministic.
+    pub fn select<'a>(
+        &self,
+        measurements: &'a [(String, FinalMeasurement)],
+    ) -> Vec<&'a (String, FinalMeasurement)> {
+        let cmp = |a: &&(String, FinalMeasurement), b: &&(String, FinalMeasurement)| {
+            let (x, y) = (self.statistic.value(&a.1), self.statistic.value(&b.1));
+            let ord = match self.rank {
+                Rank::Top => y.total_cmp(&x),
+                Rank::Bottom => x.total_cmp(&y),
+            };
+            match ord {
+                Ordering::Equal => a.0.cmp(&b.0),
+                ord => ord,
+            }
+        };
+
+        if self.n == 0 {
+            return vec![];
+        }
+
+        let mut selected: Vec<_> = measurements.iter().collect();
+        if self.n < selected.len() {
+            selected.select_nth_unstable_by(self.n - 1, cmp);
+            selected.truncate(self.n);
+        }
+        selected.sort_unstable_by(cmp);
+        selected
+    }
+
+    /// Print the selected stations as a compact, aligned table.
+    pub fn write(
+        &self,
+        output: &mut impl Write,
+        measurements: &[(String, FinalMeasurement)],
+    ) -> std::io::Result<()> {
+        let selected = self.select(measurements);
+
+        let rank_width = selected.len().to_string().len().max(1);
+        let name_width = selected
+            .iter()
+            .map(|(city, _)| display_width(city))
+            .max()
+            .unwrap_or(0)
+            .max("station".len());
+
+        writeln!(
+            output,
+            "{:>rank_width$}  {:<name_width$}  {:>8}",
+            "#",
+            "station",
+            self.statistic.name()
+        )?;
+        for (i, (city, measurement)) in selected.iter().enumerate() {
+            let padding = name_width - display_width(city);
+            write!(output, "{:>rank_width$}  {city}{:padding$}  ", i + 1, "")?;
+
+            let value = self.statistic.value(measurement);
+            match self.statistic {
+                Statistic::Count => writeln!(output, "{:>8}", measurement.count)?,
+                Statistic::StdDev => writeln!(output, "{value:>8.2}")?,
+                _ => writeln!(output, "{value:>8.1}")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Rank, Report, Statistic};
+    use crate::measurement::{FinalMeasurement, Measurement};
+
+    fn stations(values: &[(&str, i32)]) -> Vec<(String, FinalMeasurement)> {
+        values
+            .iter()
+            .map(|&(city, value)| (city.to_string(), Measurement::new(value).into()))
+            .collect()
+    }
+
+    fn names(selected: &[&(String, FinalMeasurement)]) -> Vec<String> {
+        selected.iter().map(|(city, _)| city.clone()).collect()
+    }
+
+    #[test]
+    fn test_select_breaks_ties_by_name() {
+        let measurements = stations(&[("Oslo", 50), ("Abha", 50), ("Lima", 90), ("Cairo", 50)]);
+
+        let top = Report::new(Rank::Top, Statistic::Avg, 3).select(&measurements);
+        assert_eq!(names(&top), ["Lima", "Abha", "Cairo"]);
+
+        let bottom = Report::new(Rank::Bottom, Statistic::Avg, 2).select(&measurements);
+        assert_eq!(names(&bottom), ["Abha", "Cairo"]);
+    }
+
+    #[test]
+    fn test_select_more_than_there_are() {
+        let measurements = stations(&[("Oslo", -30), ("Abha", 250)]);
+
+        let top = Report::new(Rank::Top, Statistic::Max, 10).select(&measurements);
+        assert_eq!(names(&top), ["Abha", "Oslo"]);
+        assert!(
+            Report::new(Rank::Top, Statistic::Max, 0)
+                .select(&measurements)
+                .is_empty()
+        );
+        assert!(
+            Report::new(Rank::Top, Statistic::Max, 3)
+                .select(&[])
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_write_aligns_columns() {
+        let measurements = stations(&[("Zürich", 95), ("Abha", 180), ("Oslo", -30)]);
+
+        let mut actual = Vec::new();
+        Report::new(Rank::Bottom, Statistic::Min, 2)
+            .write(&mut actual, &measurements)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(actual).unwrap(),
+            "#  station       min\n\
+             1  Oslo         -3.0\n\
+             2  Zürich        9.5\n"
+        );
+    }
+}
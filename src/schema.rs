@@ -0,0 +1,176 @@
+use anyhow::{anyhow, bail};
+
+/// Delimiters with a specialised SIMD scan; see `File::visit_rows`.
+pub const DELIMITERS: &[u8] = b";,\t| :";
+
+/// Layout of the delimited records in the input file.
+///
+/// The default is the challenge's `name;temperature` and takes the SWAR fast
+/// path. Any other layout is read with a general record parser that accepts
+/// plain decimals (`42`, `-3.14`) in the value column, stored as tenths.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Schema {
+    pub(crate) delimiter: u8,
+    pub(crate) key_column: usize,
+    pub(crate) value_columns: Vec<usize>,
+    pub(crate) timestamp_column: Option<usize>,
+    pub(crate) skip_header: bool,
+}
+
+impl Default for Schema {
+    #[inline(always)]
+    fn default() -> Self {
+        Self {
+            delimiter: b';',
+            key_column: 0,
+            value_columns: vec![1],
+            timestamp_column: None,
+            skip_header: false,
+        }
+    }
+}
+
+impl Schema {
+    /// Columns are numbered from 0. With more than one value column, every
+    /// key keeps an independent aggregate per column.
+    pub fn new(
+        delimiter: u8,
+        key_column: usize,
+        value_columns: Vec<usize>,
+        skip_header: bool,
+    ) -> anyhow::Result<Self> {
+        if !DELIMITERS.contains(&delimiter) {
+            bail!(
+                "unsupported delimiter {:?}, expected one of {:?}",
+                delimiter as char,
+                std::str::from_utf8(DELIMITERS).unwrap()
+            );
+        }
+        if value_columns.is_empty() {
+            bail!("at least one value column is required");
+        }
+        if value_columns.contains(&key_column) {
+            bail!("key and value must be different columns");
+        }
+        for (i, column) in value_columns.iter().enumerate() {
+            if value_columns[..i].contains(column) {
+                bail!("value column {column} is listed twice");
+            }
+        }
+
+        Ok(Self {
+            delimiter,
+            key_column,
+            value_columns,
+            timestamp_column: None,
+            skip_header,
+        })
+    }
+
+    /// Read a timestamp from `column` on every row, for aggregation per time
+    /// window. Only a single value column is supported alongside it.
+    pub fn with_timestamp_column(mut self, column: usize) -> anyhow::Result<Self> {
+        if column == self.key_column || self.value_columns.contains(&column) {
+            bail!("the timestamp must be in its own column");
+        }
+        if self.value_columns.len() > 1 {
+            bail!("time windows need a single value column");
+        }
+
+        self.timestamp_column = Some(column);
+        Ok(self)
+    }
+
+    #[inline(always)]
+    pub fn value_columns(&self) -> &[usize] {
+        &self.value_columns
+    }
+
+    #[inline(always)]
+    pub fn timestamp_column(&self) -> Option<usize> {
+        self.timestamp_column
+    }
+
+    #[inline(always)]
+    pub fn last_column(&self) -> usize {
+        self.value_columns
+            .iter()
+            .copied()
+            .chain(self.timestamp_column)
+            .fold(self.key_column, usize::max)
+    }
+
+    /// Whether the SWAR challenge parser applies.
+    #[inline(always)]
+    pub fn is_challenge(&self) -> bool {
+        self.delimiter == b';'
+            && self.key_column == 0
+            && self.value_columns == [1]
+            && self.timestamp_column.is_none()
+            && !self.skip_header
+    }
+}
+
+/// Parse a delimiter given on the command line: a single ASCII character, or `tab`.
+pub fn parse_delimiter(s: &str) -> anyhow::Result<u8> {
+    match s {
+        "tab" | "\\t" => Ok(b'\t'),
+        "space" => Ok(b' '),
+        _ if s.len() == 1 => Ok(s.as_bytes()[0]),
+        _ => Err(anyhow!("delimiter must be a single character: {s:?}")),
+    }
+}
+
+/// Parse a plain decimal (`42`, `-3.14`, `+7.`) into tenths, rounding half away
+/// from zero. Returns `None` for anything else or values outside `i32`.
+pub fn parse_value(field: &[u8]) -> Option<i32> {
+    let field = field.trim_ascii();
+    let (negative, digits) = match field {
+        [b'-', rest @ ..] => (true, rest),
+        [b'+', rest @ ..] => (false, rest),
+        _ => (false, field),
+    };
+    let (whole, fraction) = match digits.iter().position(|&b| b == b'.') {
+        Some(dot) => (&digits[..dot], &digits[dot + 1..]),
+        None => (digits, &[][..]),
+    };
+    if whole.is_empty() && fraction.is_empty() {
+        return None;
+    }
+    if !whole.iter().chain(fraction).all(u8::is_ascii_digit) {
+        return None;
+    }
+
+    let mut tenths: i64 = 0;
+    for &digit in whole {
+        tenths = tenths * 10 + (digit - b'0') as i64;
+        if tenths > i32::MAX as i64 {
+            return None;
+        }
+    }
+    tenths = tenths * 10 + fraction.first().map_or(0, |&d| (d - b'0') as i64);
+    if fraction.get(1).is_some_and(|&d| d >= b'5') {
+        tenths += 1;
+    }
+
+    i32::try_from(if negative { -tenths } else { tenths }).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_value;
+
+    #[test]
+    fn test_parse_value() {
+        assert_eq!(parse_value(b"42"), Some(420));
+        assert_eq!(parse_value(b"-3.14"), Some(-31));
+        assert_eq!(parse_value(b"-3.15"), Some(-32));
+        assert_eq!(parse_value(b" +7. "), Some(70));
+        assert_eq!(parse_value(b".5"), Some(5));
+        assert_eq!(parse_value(b"12345.6"), Some(123456));
+        assert_eq!(parse_value(b""), None);
+        assert_eq!(parse_value(b"-"), None);
+        assert_eq!(parse_value(b"1e3"), None);
+        assert_eq!(parse_value(b"99999999999"), None);
+    }
+}
@@ -0,0 +1,178 @@
+use std::str::FromStr;
+
+use unicode_normalization::{UnicodeNormalization, char::is_combining_mark};
+
+use crate::{measurement::FinalMeasurement, report::Statistic};
+
+/// Order of the final station list.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Raw UTF-8 byte order, as the challenge expects.
+    #[default]
+    Bytes,
+    /// Unicode scalar value order. Gives the same order as `Bytes`, since UTF-8
+    /// preserves it; kept as an explicit spelling of the intent.
+    Codepoint,
+    /// Unicode lowercase, ties broken by byte order.
+    CaseInsensitive,
+    /// Accents and case folded away (`Abéché` sorts as `abeche`), ties broken by byte order.
+    Collation,
+    /// By a statistic, ascending, ties broken by byte order.
+    Statistic(Statistic),
+}
+
+impl FromStr for SortOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "bytes" | "byte" => Ok(SortOrder::Bytes),
+            "codepoint" | "unicode" => Ok(SortOrder::Codepoint),
+            "case-insensitive" | "nocase" => Ok(SortOrder::CaseInsensitive),
+            "collation" | "collate" => Ok(SortOrder::Collation),
+            _ => Ok(SortOrder::Statistic(s.parse()?)),
+        }
+    }
+}
+
+impl SortOrder {
+    /// Whether the order only looks at station names, so it applies to rows
+    /// without a [`FinalMeasurement`] as well.
+    pub fn is_by_name(&self) -> bool {
+        !matches!(self, SortOrder::Statistic(_))
+    }
+}
+
+/// Sort the station list in place. `reverse` flips the whole order, tie breaks included.
+pub fn sort(measurements: &mut [(String, FinalMeasurement)], order: SortOrder, reverse: bool) {
+    match order {
+        SortOrder::Statistic(statistic) => {
+            measurements.sort_unstable_by(|a, b| {
+                statistic
+                    .value(&a.1)
+                    .total_cmp(&statistic.value(&b.1))
+                    .then_with(|| a.0.cmp(&b.0))
+            });
+            if reverse {
+                measurements.reverse();
+            }
+        }
+        _ => sort_by_name(measurements, order, reverse),
+    }
+}
+
+/// Sort any rows keyed by station name in place, like [`sort`]. A statistic
+/// order, see [`SortOrder::is_by_name`], falls back to byte order.
+pub fn sort_by_name<T>(rows: &mut [(String, T)], order: SortOrder, reverse: bool) {
+    match order {
+        SortOrder::Bytes | SortOrder::Statistic(_) => rows.sort_unstable_by(|a, b| a.0.cmp(&b.0)),
+        SortOrder::Codepoint => rows.sort_unstable_by(|a, b| a.0.chars().cmp(b.0.chars())),
+        SortOrder::CaseInsensitive => {
+            rows.sort_by_cached_key(|(city, _)| (city.to_lowercase(), city.clone()))
+        }
+        SortOrder::Collation => {
+            rows.sort_by_cached_key(|(city, _)| (collation_key(city), city.clone()))
+        }
+    }
+
+    if reverse {
+        rows.reverse();
+    }
+}
+
+/// Decompose, drop combining marks and lowercase, spelling out the letters
+/// that have no canonical decomposition (`ø`, `ł`, `ß`, ...).
+pub fn collation_key(name: &str) -> String {
+    let mut key = String::with_capacity(name.len());
+    for c in name.nfd().filter(|c| !is_combining_mark(*c)) {
+        match c {
+            'Ø' | 'ø' => key.push('o'),
+            'Ł' | 'ł' => key.push('l'),
+            'Đ' | 'đ' => key.push('d'),
+            'ı' => key.push('i'),
+            'ß' => key.push_str("ss"),
+            'Æ' | 'æ' => key.push_str("ae"),
+            'Œ' | 'œ' => key.push_str("oe"),
+            'Þ' | 'þ' => key.push_str("th"),
+            _ => key.extend(c.to_lowercase()),
+        }
+    }
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SortOrder, collation_key, sort};
+    use crate::{
+        measurement::{FinalMeasurement, Measurement},
+        report::Statistic,
+    };
+
+    fn sorted(order: &str, reverse: bool) -> Vec<String> {
+        let mut measurements: Vec<(String, FinalMeasurement)> = [
+            ("abha", 50),
+            ("Zürich", 10),
+            ("Abéché", 300),
+            ("Oslo", 10),
+            ("Abha", 50),
+            ("Åre", -20),
+        ]
+        .into_iter()
+        .map(|(city, value)| (city.to_string(), Measurement::new(value).into()))
+        .collect();
+
+        sort(&mut measurements, order.parse().unwrap(), reverse);
+        measurements.into_iter().map(|(city, _)| city).collect()
+    }
+
+    #[test]
+    fn test_collation_key_folds_accents_and_case() {
+        assert_eq!(collation_key("Abéché"), "abeche");
+        assert_eq!(collation_key("Zürich"), "zurich");
+        assert_eq!(collation_key("Tromsø"), "tromso");
+        assert_eq!(collation_key("Wrocław"), "wroclaw");
+        assert_eq!(collation_key("Ürümqi"), collation_key("URUMQI"));
+    }
+
+    #[test]
+    fn test_codepoint_matches_bytes() {
+        assert_eq!(sorted("codepoint", false), sorted("bytes", false));
+        assert_eq!(
+            sorted("bytes", false),
+            ["Abha", "Abéché", "Oslo", "Zürich", "abha", "Åre"]
+        );
+    }
+
+    #[test]
+    fn test_case_insensitive_breaks_ties_by_bytes() {
+        assert_eq!(
+            sorted("nocase", false),
+            ["Abha", "abha", "Abéché", "Oslo", "Zürich", "Åre"]
+        );
+        assert_eq!(
+            sorted("collation", false),
+            ["Abéché", "Abha", "abha", "Åre", "Oslo", "Zürich"]
+        );
+    }
+
+    #[test]
+    fn test_statistic_breaks_ties_by_bytes() {
+        assert_eq!(
+            "max".parse::<SortOrder>().unwrap(),
+            SortOrder::Statistic(Statistic::Max)
+        );
+        assert_eq!(
+            sorted("max", false),
+            ["Åre", "Oslo", "Zürich", "Abha", "abha", "Abéché"]
+        );
+    }
+
+    #[test]
+    fn test_reverse_flips_tie_breaks_too() {
+        for order in ["bytes", "nocase", "max"] {
+            let mut expected = sorted(order, false);
+            expected.reverse();
+            assert_eq!(sorted(order, true), expected, "{order}");
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use std::{collections::BTreeMap, io::Write, str::FromStr};
+
+use anyhow::{anyhow, bail};
+
+use crate::{measurement::Measurement, output::Writer};
+
+/// Per-station time series: one aggregate per window, keyed by the window
+/// start in seconds since the Unix epoch.
+pub type Series = BTreeMap<i64, Measurement>;
+
+/// Fixed-length aggregation window, aligned to the Unix epoch (UTC).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Window {
+    seconds: i64,
+}
+
+impl Window {
+    pub const HOURLY: Window = Window { seconds: 3600 };
+    pub const DAILY: Window = Window { seconds: 86400 };
+
+    pub fn new(seconds: i64) -> anyhow::Result<Self> {
+        if seconds <= 0 {
+            bail!("window must be at least one second long");
+        }
+        Ok(Self { seconds })
+    }
+
+    #[inline(always)]
+    pub fn seconds(&self) -> i64 {
+        self.seconds
+    }
+
+    /// Start of the window `timestamp` falls in.
+    #[inline(always)]
+    pub fn start(&self, timestamp: i64) -> i64 {
+        timestamp.div_euclid(self.seconds) * self.seconds
+    }
+}
+
+impl Default for Window {
+    #[inline(always)]
+    fn default() -> Self {
+        Self::HOURLY
+    }
+}
+
+impl FromStr for Window {
+    type Err = anyhow::Error;
+
+    /// `hourly`, `daily`, or a length such as `90s`, `15m`, `6h` or `2d`.
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let s = s.trim().to_ascii_lowercase();
+        match s.as_str() {
+            "hourly" | "hour" => return Ok(Self::HOURLY),
+            "daily" | "day" => return Ok(Self::DAILY),
+            _ => {}
+        }
+
+        let unit_at = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (amount, unit) = s.split_at(unit_at);
+        let amount: i64 = amount.parse().map_err(|_| anyhow!("unknown window: {s}"))?;
+        let unit = match unit {
+            "" | "s" => 1,
+            "m" => 60,
+            "h" => 3600,
+            "d" => 86400,
+            _ => bail!("unknown window unit in {s}, expected s, m, h or d"),
+        };
+        Self::new(amount.saturating_mul(unit))
+    }
+}
+
+/// Parse epoch seconds (`1700000000`, fraction truncated) or ISO 8601
+/// (`2024-03-01`, `2024-03-01T13:45:00Z`, `2024-03-01 13:45:00.5+02:00`) into
+/// seconds since the Unix epoch. Timestamps without an offset are UTC.
+pub fn parse_timestamp(field: &[u8]) -> Option<i64> {
+    let field = field.trim_ascii();
+    if field.len() >= 10 && field[4] == b'-' {
+        return parse_iso8601(field);
+    }
+
+    let (negative, digits) = match field {
+        [b'-', rest @ ..] => (true, rest),
+        _ => (false, field),
+    };
+    let whole = match digits.iter().position(|&b| b == b'.') {
+        Some(dot) if digits[dot + 1..].iter().all(u8::is_ascii_digit) => &digits[..dot],
+        Some(_) => return None,
+        None => digits,
+    };
+    let seconds = parse_digits(whole)?;
+    Some(if negative { -seconds } else { seconds })
+}
+
+fn parse_iso8601(field: &[u8]) -> Option<i64> {
+    let year = parse_digits(&field[0..4])?;
+    let month = parse_number(field, 5, b'-')?;
+    let day = parse_number(field, 8, b'-')?;
+    if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+        return None;
+    }
+    let mut seconds = days_from_civil(year, month, day) * 86400;
+
+    let mut rest = &field[10..];
+    if let [b'T' | b't' | b' ', time @ ..] = rest {
+        let hour = parse_digits(time.get(0..2)?)?;
+        let minute = parse_number(time, 3, b':')?;
+        rest = &time[5..];
+        let mut second = 0;
+        if let [b':', tail @ ..] = rest {
+            second = parse_digits(tail.get(0..2)?)?;
+            rest = &tail[2..];
+            if let [b'.' | b',', tail @ ..] = rest {
+                let fraction = tail.iter().take_while(|b| b.is_ascii_digit()).count();
+                if fraction == 0 {
+                    return None;
+                }
+                rest = &tail[fraction..];
+            }
+        }
+        if hour > 23 || minute > 59 || second > 60 {
+            return None;
+        }
+        seconds += hour * 3600 + minute * 60 + second;
+    }
+
+    let offset = match rest {
+        [] | [b'Z' | b'z'] => 0,
+        [sign @ (b'+' | b'-'), offset @ ..] => {
+            let hours = parse_digits(offset.get(0..2)?)?;
+            let minutes = match &offset[2..] {
+                [] => 0,
+                [b':', minutes @ ..] | minutes if minutes.len() == 2 => parse_digits(minutes)?,
+                _ => return None,
+            };
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            let offset = hours * 3600 + minutes * 60;
+            if *sign == b'+' { offset } else { -offset }
+        }
+        _ => return None,
+    };
+
+    Some(seconds - offset)
+}
+
+/// Two digits at `at`, preceded by `separator`.
+#[inline(always)]
+fn parse_number(field: &[u8], at: usize, separator: u8) -> Option<i64> {
+    if field.get(at - 1) != Some(&separator) {
+        return None;
+    }
+    parse_digits(field.get(at..at + 2)?)
+}
+
+#[inline(always)]
+fn parse_digits(digits: &[u8]) -> Option<i64> {
+    if digits.is_empty() || digits.len() > 18 || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    Some(
+        digits
+            .iter()
+            .fold(0, |value, &digit| value * 10 + (digit - b'0') as i64),
+    )
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 in the proleptic Gregorian calendar (Howard Hinnant's algorithm).
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Inverse of [`days_from_civil`].
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+/// `timestamp` as `YYYY-MM-DDTHH:MM:SSZ`.
+pub fn format_timestamp(timestamp: i64) -> String {
+    let (year, month, day) = civil_from_days(timestamp.div_euclid(86400));
+    let seconds = timestamp.rem_euclid(86400);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// One section per station, with one row per window named by its start time.
+pub fn write(
+    output: &mut impl Write,
+    stations: &[(String, Series)],
+    writer: &Writer,
+) -> std::io::Result<()> {
+    let sections: Vec<_> = stations
+        .iter()
+        .map(|(city, series)| {
+            let rows = series
+                .iter()
+                .map(|(start, measurement)| (format_timestamp(*start), (*measurement).into()))
+                .collect();
+            (city.clone(), rows)
+        })
+        .collect();
+    writer.write_sections(output, "station", &sections)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Series, Window, format_timestamp, parse_timestamp};
+    use crate::{
+        measurement::Measurement,
+        output::{Format, Writer},
+    };
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp(b"1700000000"), Some(1_700_000_000));
+        assert_eq!(parse_timestamp(b"1700000000.75"), Some(1_700_000_000));
+        assert_eq!(parse_timestamp(b"1970-01-01"), Some(0));
+        assert_eq!(
+            parse_timestamp(b"2023-11-14T22:13:20Z"),
+            Some(1_700_000_000)
+        );
+        assert_eq!(
+            parse_timestamp(b"2023-11-15 00:13:20.5+02:00"),
+            Some(1_700_000_000)
+        );
+        assert_eq!(parse_timestamp(b"2024-02-29T12:00"), Some(1_709_208_000));
+        assert_eq!(parse_timestamp(b"1969-12-31T23:00:00-0100"), Some(0));
+        assert_eq!(parse_timestamp(b"2023-02-29"), None);
+        assert_eq!(parse_timestamp(b"2023-11-14T25:00:00Z"), None);
+        assert_eq!(parse_timestamp(b"yesterday"), None);
+    }
+
+    #[test]
+    fn test_parse_timestamp_checks_every_separator() {
+        for malformed in [
+            "2023-11x14",
+            "2023x11-14",
+            "2023-11-14x",
+            "2023-11-14T22x13:20Z",
+            "2023-11-14T22:13x20Z",
+            "2023-11-14T2213:20Z",
+            "2023-11-14T22:13:20.Z",
+            "2023-11-14T22:13:20x",
+            "2023-11-14T22:13:20+02x00",
+            "2023-11-14T22:13:20+020",
+            "2023-11-14T22:13:20+24:00",
+            "2023-11-14T22:13:20+02:60",
+        ] {
+            assert_eq!(parse_timestamp(malformed.as_bytes()), None, "{malformed}");
+        }
+    }
+
+    #[test]
+    fn test_windows_align_to_epoch() {
+        let window: Window = "15m".parse().unwrap();
+        assert_eq!(window.seconds(), 900);
+        assert_eq!(window.start(1_700_000_000), 1_699_999_200);
+        assert_eq!(Window::DAILY.start(-1), -86400);
+        assert_eq!(format_timestamp(1_699_999_200), "2023-11-14T22:00:00Z");
+        assert_eq!(format_timestamp(-86400), "1969-12-31T00:00:00Z");
+        assert!("0h".parse::<Window>().is_err());
+    }
+
+    #[test]
+    fn test_write_is_one_document() {
+        let series = |value| Series::from([(0, Measurement::new(value))]);
+        let stations = vec![
+            ("Abha".to_string(), series(10)),
+            ("Oslo".to_string(), series(-20)),
+        ];
+        let writer = Writer::new(Format::Tsv, false).with_label("window");
+
+        let mut actual = Vec::new();
+        super::write(&mut actual, &stations, &writer).unwrap();
+        assert_eq!(
+            String::from_utf8(actual).unwrap(),
+            "station\twindow\tmin\tmean\tmax\tcount\n\
+             Abha\t1970-01-01T00:00:00Z\t1.0\t1.0\t1.0\t1\n\
+             Oslo\t1970-01-01T00:00:00Z\t-2.0\t-2.0\t-2.0\t1\n"
+        );
+    }
+}